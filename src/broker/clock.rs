@@ -0,0 +1,79 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(test)]
+use std::sync::Mutex;
+
+// everything time based in the brokers goes through this, so tests can run it without waiting
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn system_time(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// only moves when told to, sleeping moves it forward right away
+#[cfg(test)]
+pub struct MockClock {
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), SystemTime::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{Clock, MockClock};
+
+    #[test]
+    fn sleeps_without_waiting() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let system_start = clock.system_time();
+        clock.sleep(Duration::from_secs(3600));
+        assert_eq!(clock.now() - start, Duration::from_secs(3600));
+        assert_eq!(clock.system_time().duration_since(system_start).unwrap(), Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use serde::Serialize;
+use serde_json::Value;
+use grin_core::libtx::slate::Slate;
+
+use common::{Error, Wallet713Error};
+use super::slate_version::{convert_slate, check_slate_version, slate_version};
+
+// how slates are turned into the string that gets encrypted and posted, and back
+pub trait SlateCodec: Send + Sync {
+    fn encode(&self, slate: &Slate) -> Result<String, Error>;
+    // fails with `SlateVersionMismatch` or `UnsupportedSlateVersion` for slates of a version
+    // this codec can not handle
+    fn decode(&self, slate_str: &str) -> Result<Slate, Error>;
+    // encodes for a peer that only understands slates up to `max_version`
+    fn encode_for_version(&self, _slate: &Slate, max_version: u64) -> Result<String, Error> {
+        Err(Wallet713Error::SlateDowngradeFailed(max_version).into())
+    }
+}
+
+pub struct JsonCodec {
+    target_slate_version: Option<u64>,
+    pretty: bool,
+}
+
+impl JsonCodec {
+    pub fn new(target_slate_version: Option<u64>) -> Self {
+        Self {
+            target_slate_version,
+            pretty: false,
+        }
+    }
+
+    // indented and on several lines, easier to read but larger to send
+    pub fn with_pretty_printing(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    fn to_json<T: Serialize>(&self, value: &T) -> Result<String, Error> {
+        let json = match self.pretty {
+            true => serde_json::to_string_pretty(value)?,
+            false => serde_json::to_string(value)?,
+        };
+        Ok(json)
+    }
+}
+
+impl SlateCodec for JsonCodec {
+    fn encode(&self, slate: &Slate) -> Result<String, Error> {
+        self.to_json(slate)
+    }
+
+    fn encode_for_version(&self, slate: &Slate, max_version: u64) -> Result<String, Error> {
+        let value = serde_json::to_value(slate)?;
+        if slate_version(&value) <= max_version {
+            return self.to_json(&value);
+        }
+        // only conversions between known versions are attempted, and those are lossless
+        let value = convert_slate(value, max_version).map_err(|_| Wallet713Error::SlateDowngradeFailed(max_version))?;
+        self.to_json(&value)
+    }
+
+    fn decode(&self, slate_str: &str) -> Result<Slate, Error> {
+        let value: Value = serde_json::from_str(slate_str)?;
+        let version = check_slate_version(&value)?;
+        let value = match self.target_slate_version {
+            Some(target) => convert_slate(value, target).map_err(|_| Wallet713Error::UnsupportedSlateVersion(version))?,
+            None => value,
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// wraps a codec for sending to a recipient known to only understand older slates
+pub struct DowngradingCodec {
+    inner: Arc<SlateCodec>,
+    max_version: u64,
+}
+
+impl DowngradingCodec {
+    pub fn new(inner: Arc<SlateCodec>, max_version: u64) -> Self {
+        Self {
+            inner,
+            max_version,
+        }
+    }
+}
+
+impl SlateCodec for DowngradingCodec {
+    fn encode(&self, slate: &Slate) -> Result<String, Error> {
+        self.inner.encode_for_version(slate, self.max_version)
+    }
+
+    fn decode(&self, slate_str: &str) -> Result<Slate, Error> {
+        self.inner.decode(slate_str)
+    }
+}
+
+// the version of a slate the codec rejected, if that is why it was rejected
+pub fn rejected_slate_version(e: &Error) -> Option<u64> {
+    match e.downcast_ref::<Wallet713Error>() {
+        Some(&Wallet713Error::SlateVersionMismatch(version, _)) => Some(version),
+        Some(&Wallet713Error::UnsupportedSlateVersion(version)) => Some(version),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+    use grin_core::libtx::slate::Slate;
+    use super::{JsonCodec, SlateCodec, rejected_slate_version};
+
+    #[test]
+    fn reports_rejected_slate_versions() {
+        let codec = JsonCodec::new(None);
+        let e = codec.decode("{ \"version\": 7 }").unwrap_err();
+        assert_eq!(rejected_slate_version(&e), Some(7));
+        let e = codec.decode("not a slate").unwrap_err();
+        assert_eq!(rejected_slate_version(&e), None);
+    }
+
+    #[test]
+    fn downgrades_slates_for_older_peers() {
+        let codec = JsonCodec::new(None);
+        let encoded = codec.encode_for_version(&Slate::blank(2), 0).unwrap();
+        let value: Value = serde_json::from_str(&encoded).unwrap();
+        assert!(value.get("version").is_none());
+    }
+
+    #[test]
+    fn pretty_prints_on_request() {
+        let slate = Slate::blank(2);
+        let compact = JsonCodec::new(None).encode(&slate).unwrap();
+        let pretty = JsonCodec::new(None).with_pretty_printing(true).encode(&slate).unwrap();
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains('\n'));
+        let compact: Value = serde_json::from_str(&compact).unwrap();
+        let pretty: Value = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(compact, pretty);
+    }
+}
@@ -0,0 +1,41 @@
+use std::io::{Read, Write};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use common::{Error, Wallet713Error};
+use common::crypto::{to_hex, from_hex};
+
+// compressed slates are hex encoded so they can travel wherever a slate string could
+pub fn compress_slate(slate: &str) -> Result<String, Error> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(slate.as_bytes()).map_err(|_| Wallet713Error::Compression)?;
+    let compressed = encoder.finish().map_err(|_| Wallet713Error::Compression)?;
+    Ok(to_hex(compressed))
+}
+
+// stops reading one byte past `limit` so the caller can tell an oversized slate apart without
+// inflating all of it
+pub fn decompress_slate(compressed: &str, limit: usize) -> Result<String, Error> {
+    let compressed = from_hex(compressed.to_string()).map_err(|_| Wallet713Error::Decompression)?;
+    let mut slate = String::new();
+    DeflateDecoder::new(&compressed[..])
+        .take(limit as u64 + 1)
+        .read_to_string(&mut slate)
+        .map_err(|_| Wallet713Error::Decompression)?;
+    Ok(slate)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress_slate, decompress_slate};
+
+    #[test]
+    fn can_compress_and_decompress_slates() {
+        let slate = json!({ "id": "test", "amount": 1000 }).to_string();
+        let compressed = compress_slate(&slate).unwrap();
+        assert_eq!(decompress_slate(&compressed, 1024).unwrap(), slate);
+        assert_eq!(decompress_slate(&compressed, 4).unwrap().len(), 5);
+        assert!(decompress_slate("not a slate", 1024).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SlateDirection {
+    Outgoing,
+    Incoming,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SlateState {
+    // posted by us, waiting for the answer
+    Sent,
+    // came in from a peer without us having sent it first
+    Received,
+    // came back from the peer we sent it to
+    Answered,
+}
+
+#[derive(Clone, Debug)]
+pub struct Correlation {
+    pub direction: SlateDirection,
+    pub peer: String,
+    pub state: SlateState,
+    pub recorded_at: Instant,
+}
+
+// an `Instant` means nothing to another process, so a saved entry keeps its age instead
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedCorrelation {
+    pub slate_id: String,
+    pub direction: SlateDirection,
+    pub peer: String,
+    pub state: SlateState,
+    pub age_ms: u64,
+}
+
+// remembers the slates that went through the broker by id, for as long as `ttl`, so an
+// incoming slate can be told apart as the answer to one we sent or a new request
+pub struct CorrelationStore {
+    ttl: Duration,
+    entries: HashMap<String, Correlation>,
+}
+
+impl CorrelationStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn record_outgoing(&mut self, slate_id: &str, peer: &str, now: Instant) {
+        self.expire(now);
+        self.entries.insert(slate_id.to_string(), Correlation {
+            direction: SlateDirection::Outgoing,
+            peer: peer.to_string(),
+            state: SlateState::Sent,
+            recorded_at: now,
+        });
+    }
+
+    // returns `Answered` when this is the answer to a slate we sent, `Received` otherwise
+    pub fn record_incoming(&mut self, slate_id: &str, peer: &str, now: Instant) -> SlateState {
+        self.expire(now);
+        let state = match self.entries.get(slate_id) {
+            Some(ref entry) if entry.direction == SlateDirection::Outgoing => SlateState::Answered,
+            _ => SlateState::Received,
+        };
+        self.entries.insert(slate_id.to_string(), Correlation {
+            direction: SlateDirection::Incoming,
+            peer: peer.to_string(),
+            state,
+            recorded_at: now,
+        });
+        state
+    }
+
+    pub fn get(&self, slate_id: &str, now: Instant) -> Option<Correlation> {
+        self.entries.get(slate_id)
+            .filter(|entry| now.duration_since(entry.recorded_at) < self.ttl)
+            .cloned()
+    }
+
+    // the same slate coming in again from the same peer, e.g. redelivered after a reconnect
+    pub fn is_duplicate(&self, slate_id: &str, peer: &str, now: Instant) -> bool {
+        match self.get(slate_id, now) {
+            Some(entry) => entry.direction == SlateDirection::Incoming && entry.peer == peer,
+            None => false,
+        }
+    }
+
+    pub fn snapshot(&self, now: Instant) -> Vec<SavedCorrelation> {
+        self.entries.iter()
+            .filter(|&(_, entry)| now.duration_since(entry.recorded_at) < self.ttl)
+            .map(|(slate_id, entry)| {
+                let age = now.duration_since(entry.recorded_at);
+                SavedCorrelation {
+                    slate_id: slate_id.clone(),
+                    direction: entry.direction,
+                    peer: entry.peer.clone(),
+                    state: entry.state,
+                    age_ms: age.as_secs() * 1000 + age.subsec_millis() as u64,
+                }
+            })
+            .collect()
+    }
+
+    // what is known already wins over what was saved
+    pub fn restore(&mut self, saved: Vec<SavedCorrelation>, now: Instant) {
+        for entry in saved {
+            let recorded_at = match now.checked_sub(Duration::from_millis(entry.age_ms)) {
+                Some(x) => x,
+                None => continue,
+            };
+            self.entries.entry(entry.slate_id).or_insert(Correlation {
+                direction: entry.direction,
+                peer: entry.peer,
+                state: entry.state,
+                recorded_at,
+            });
+        }
+        self.expire(now);
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, entry| now.duration_since(entry.recorded_at) < ttl);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use super::{CorrelationStore, SlateDirection, SlateState};
+
+    #[test]
+    fn classifies_incoming_slates() {
+        let now = Instant::now();
+        let mut store = CorrelationStore::new(Duration::from_secs(60));
+        store.record_outgoing("a", "alice", now);
+        assert_eq!(store.get("a", now).unwrap().direction, SlateDirection::Outgoing);
+        assert_eq!(store.record_incoming("a", "alice", now), SlateState::Answered);
+        assert_eq!(store.record_incoming("b", "bob", now), SlateState::Received);
+        assert_eq!(store.get("a", now).unwrap().state, SlateState::Answered);
+    }
+
+    #[test]
+    fn forgets_slates_after_the_ttl() {
+        let now = Instant::now();
+        let mut store = CorrelationStore::new(Duration::from_secs(60));
+        store.record_outgoing("a", "alice", now);
+        let later = now + Duration::from_secs(61);
+        assert!(store.get("a", later).is_none());
+        assert_eq!(store.record_incoming("a", "alice", later), SlateState::Received);
+        assert_eq!(store.entries.len(), 1);
+    }
+
+    #[test]
+    fn restores_a_snapshot() {
+        let now = Instant::now();
+        let mut store = CorrelationStore::new(Duration::from_secs(60));
+        store.record_outgoing("a", "alice", now);
+        store.record_incoming("b", "bob", now);
+        let later = now + Duration::from_secs(30);
+        let snapshot = store.snapshot(later);
+        assert_eq!(snapshot.len(), 2);
+
+        let mut restored = CorrelationStore::new(Duration::from_secs(60));
+        restored.restore(snapshot, later);
+        assert_eq!(restored.get("a", later).unwrap().state, SlateState::Sent);
+        assert!(restored.is_duplicate("b", "bob", later));
+        assert!(!restored.is_duplicate("b", "alice", later));
+        assert!(!restored.is_duplicate("a", "alice", later));
+        // the age was carried over, so it still expires on time
+        assert!(restored.get("b", now + Duration::from_secs(61)).is_none());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+
+const QUEUE_SIZE_PER_THREAD: usize = 16;
+
+pub type Job = Box<FnMut() + Send>;
+
+// runs handler calls on a fixed number of threads. jobs with the same key always go to the same
+// thread so they run in the order they came in, jobs without one are spread round robin.
+pub struct DispatchPool {
+    workers: Mutex<Vec<SyncSender<Job>>>,
+    threads: Vec<JoinHandle<()>>,
+    next: AtomicUsize,
+}
+
+impl DispatchPool {
+    pub fn new(threads: usize) -> Self {
+        let (workers, threads) = (0..threads.max(1)).map(|_| {
+            let (sender, receiver) = sync_channel::<Job>(QUEUE_SIZE_PER_THREAD);
+            let thread = thread::spawn(move || {
+                for mut job in receiver {
+                    job();
+                }
+            });
+            (sender, thread)
+        }).unzip();
+        Self {
+            workers: Mutex::new(workers),
+            threads,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // blocks while the queue of the chosen thread is full
+    pub fn dispatch(&self, key: Option<&str>, job: Job) {
+        let worker = {
+            let workers = self.workers.lock().unwrap();
+            let index = match key {
+                Some(key) => {
+                    let mut hasher = DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    (hasher.finish() % workers.len() as u64) as usize
+                },
+                None => self.next.fetch_add(1, Ordering::Relaxed) % workers.len(),
+            };
+            workers[index].clone()
+        };
+        if worker.send(job).is_err() {
+            error!("dispatch thread is gone, dropping the job");
+        }
+    }
+}
+
+// lets the threads finish what is queued before they exit
+impl Drop for DispatchPool {
+    fn drop(&mut self) {
+        self.workers.lock().unwrap().clear();
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("a dispatch thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use super::DispatchPool;
+
+    #[test]
+    fn keeps_the_order_per_key() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        {
+            let pool = DispatchPool::new(4);
+            for i in 0..20u64 {
+                let handled = handled.clone();
+                let key = format!("sender-{}", i % 2);
+                pool.dispatch(Some(&key), Box::new(move || {
+                    // the earlier jobs take longer, so they would finish last without ordering
+                    thread::sleep(Duration::from_millis(20 - i));
+                    handled.lock().unwrap().push(i);
+                }));
+            }
+        }
+        let handled = handled.lock().unwrap();
+        assert_eq!(handled.len(), 20);
+        for parity in 0..2 {
+            let order: Vec<u64> = handled.iter().cloned().filter(|i| i % 2 == parity).collect();
+            assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+}
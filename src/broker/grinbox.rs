@@ -1,44 +1,437 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde_json::Value;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, sync_channel, Sender as ChannelSender, SyncSender, TrySendError, RecvTimeoutError};
+#[cfg(test)]
+use std::sync::mpsc::Receiver;
 use std::thread;
-use ws::{connect, Sender, Handler, Handshake, Message, CloseCode, Result as WsResult, ErrorKind as WsErrorKind, Error as WsError};
+#[cfg(test)]
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ws::{Builder, Settings, Factory, WebSocket, Sender, Handler, Handshake, Message, CloseCode, Frame, OpCode, Result as WsResult, ErrorKind as WsErrorKind, Error as WsError};
 use ws::util::Token;
-use colored::*;
+use ws::deflate::DeflateHandler;
+use mio::tcp::TcpStream;
+use openssl::ssl::SslStream;
+use url::Url;
+use rand::{thread_rng, Rng};
 
+use grin_core::core::amount_to_hr_string;
 use grin_core::libtx::slate::Slate;
+use colored::*;
 
 use common::{Error, Wallet713Error};
-use common::crypto::{SecretKey, Signature, verify_signature, sign_challenge, Hex, EncryptedMessage};
-use contacts::{Address, GrinboxAddress, DEFAULT_GRINBOX_PORT};
+use common::crypto::{SecretKey, PublicKey, Signature, verify_signature, generate_secret_key, public_key_from_secret_key, Hex, EncryptedMessage, DecryptionError};
+use contacts::{Address, AddressBook, GrinboxAddress, DEFAULT_GRINBOX_PORT};
 
-use super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason};
+use super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason, BrokerStatus, ConnectionState, SlateMeta, DeliveryGuarantee, PostRecord, PendingPost, PostAuditor, SlateTransform};
 use super::protocol::{ProtocolResponse, ProtocolRequest};
+use super::config::{BrokerConfig, CapabilityDowngradePolicy, FromAddressPolicy, SelfSendPolicy, RateLimitPolicy, TlsVersion};
+use super::rate_limit::{TokenBucket, PostSlots};
+use super::validation::validate_slate;
+use super::slate_version::supported_slate_versions;
+use super::clock::{Clock, SystemClock};
+use super::codec::{SlateCodec, JsonCodec, DowngradingCodec, rejected_slate_version};
+use super::compression::{compress_slate, decompress_slate};
+use super::tls::{TlsConnector, TlsHandler};
+use super::signer::{ChallengeSigner, KeySigner};
+#[cfg(test)]
+use super::signer::AddressKeySource;
+use super::resolver::{AddressResolver, RawAddressResolver};
+use super::correlation::{CorrelationStore, SlateState};
+use super::proxy::{HttpProxy, ProxyHandler};
+use super::key_cache::PublicKeyCache;
+use super::sender_filter::SenderFilter;
+use super::subscription::Subscription;
+use super::state::{BrokerState, SavedPost};
 
 const KEEPALIVE_TOKEN: Token = Token(1);
 const KEEPALIVE_INTERVAL_MS: u64 = 30_000;
+const CONNECT_WATCHDOG_STEP_MS: u64 = 50;
+const CAPABILITY_SECURE_SUBSCRIBE: &str = "secure-subscribe";
+const CAPABILITY_CHANNEL_BINDING: &str = "channel-binding";
+const CAPABILITY_COMPRESSION: &str = "compression";
+// the relay only serves clients that can read slates bound to their addresses
+const CAPABILITY_BOUND_ENCRYPTION: &str = "bound-encryption";
+
+type SharedHandler = Arc<Mutex<Box<SubscriptionHandler + Send>>>;
+type IncomingSlate = (GrinboxAddress, Slate, SlateMeta);
+type SharedSigner = Arc<ChallengeSigner + Send + Sync>;
+type SharedCodec = Arc<SlateCodec>;
+type SharedClock = Arc<Clock>;
+#[cfg(test)]
+type SlateObserver = Box<Fn(&GrinboxAddress, &Slate, &SlateMeta) + Send>;
+type Identity = (GrinboxAddress, SecretKey, SharedSigner);
+type SharedCorrelations = Arc<Mutex<CorrelationStore>>;
+type SharedTransform = Arc<SlateTransform>;
+type SharedKeyCache = Arc<Mutex<PublicKeyCache>>;
+type SharedSenderFilter = Arc<SenderFilter>;
 
 #[derive(Clone)]
 pub struct GrinboxPublisher {
     address: GrinboxAddress,
     secret_key: SecretKey,
-    use_encryption: bool,
+    config: BrokerConfig,
+    local_subscriber: Option<GrinboxBroker>,
+    signer: Option<SharedSigner>,
+    rate_limiter: Option<Arc<Mutex<TokenBucket>>>,
+    address_book: Option<Arc<Mutex<AddressBook>>>,
+    auditor: Option<Arc<PostAuditor>>,
+    codec: Option<SharedCodec>,
+    // the newest slate version each recipient is known to understand
+    recipient_slate_versions: Arc<Mutex<HashMap<String, u64>>>,
+    clock: SharedClock,
+    failed_post: Arc<Mutex<Option<(Slate, GrinboxAddress, Option<String>, DeliveryGuarantee)>>>,
+    resolver: Arc<AddressResolver>,
+    correlations: SharedCorrelations,
+    pending_posts: Arc<Mutex<HashMap<String, PendingPost>>>,
 }
 
 impl GrinboxPublisher {
-    pub fn new(address: &GrinboxAddress, secret_key: &SecretKey, use_encryption: bool) -> Result<Self, Error> {
+    pub fn with_config(address: &GrinboxAddress, secret_key: &SecretKey, config: BrokerConfig) -> Result<Self, Error> {
+        let rate_limiter = config.max_posts_per_second
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Arc::new(Mutex::new(TokenBucket::new(rate))));
+        let correlations = Arc::new(Mutex::new(CorrelationStore::new(Duration::from_millis(config.correlation_ttl_ms))));
         Ok(Self {
             address: address.clone(),
             secret_key: secret_key.clone(),
-            use_encryption,
+            config,
+            local_subscriber: None,
+            signer: None,
+            rate_limiter,
+            address_book: None,
+            auditor: None,
+            codec: None,
+            recipient_slate_versions: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(SystemClock),
+            failed_post: Arc::new(Mutex::new(None)),
+            resolver: Arc::new(RawAddressResolver),
+            correlations,
+            pending_posts: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn ping_relay(&self, address: &GrinboxAddress) -> Result<Duration, Error> {
+        GrinboxBroker::new(self.config.clone())?.ping_relay(address)
+    }
+
+    // also remembers which slate versions the recipient understands, if the relay told
+    pub fn is_recipient_online(&self, address: &GrinboxAddress) -> Result<bool, Error> {
+        let (online, slate_versions) = GrinboxBroker::new(self.config.clone())?.query_presence(address)?;
+        if let Some(max_version) = slate_versions.into_iter().max() {
+            self.set_recipient_slate_version(address, max_version);
+        }
+        Ok(online)
+    }
+
+    // slates to `address` get downgraded to `max_version` before they are sent
+    pub fn set_recipient_slate_version(&self, address: &GrinboxAddress, max_version: u64) {
+        self.recipient_slate_versions.lock().unwrap().insert(address.stripped(), max_version);
+    }
+
+    pub fn set_address_book(&mut self, address_book: Arc<Mutex<AddressBook>>) {
+        self.address_book = Some(address_book);
+    }
+
+    // like `post_slate` but also takes `@name` of a contact in the address book, anything else
+    // goes through the address resolver
+    pub fn post_slate_to(&self, slate: &Slate, recipient: &str) -> Result<Option<String>, Error> {
+        let recipient = recipient.trim();
+        let to: Box<Address> = match self.address_book {
+            Some(ref address_book) if recipient.starts_with("@") => address_book.lock().unwrap().resolve(recipient)?,
+            None if recipient.starts_with("@") => Err(Wallet713Error::UnknownContact(recipient[1..].to_string()))?,
+            _ => Box::new(self.resolver.resolve(recipient)?),
+        };
+        self.post_slate(slate, &*to)
+    }
+
+    // the posts currently going out, and the failed one kept for `retry_last_post`
+    pub fn pending_posts(&self) -> Vec<PendingPost> {
+        let mut posts: Vec<PendingPost> = self.pending_posts.lock().unwrap().values().cloned().collect();
+        posts.sort_by_key(|post| post.enqueued_at);
+        posts
+    }
+
+    // sends the last slate that failed to post again, to the same recipient
+    pub fn retry_last_post(&self) -> Result<Option<String>, Error> {
+        let (slate, to, memo, guarantee) = self.failed_post.lock().unwrap().take().ok_or(Wallet713Error::NoPostToRetry)?;
+        self.post(&slate, &to, memo.as_ref().map(|memo| &memo[..]), guarantee)
+    }
+
+    // keeps the failed post and the slate ids seen across a restart. with a local subscriber
+    // set, the slates it received are part of it too.
+    pub fn save_state(&self, path: &Path) -> Result<(), Error> {
+        let failed_post = match *self.failed_post.lock().unwrap() {
+            Some((ref slate, ref to, ref memo, guarantee)) => {
+                let pending = self.pending_posts.lock().unwrap().get(&slate.id.to_string()).cloned();
+                Some(SavedPost {
+                    slate: slate.clone(),
+                    recipient: to.to_string(),
+                    port: to.port,
+                    memo: memo.clone(),
+                    guarantee,
+                    enqueued_at: pending.as_ref().map(|post| post.enqueued_at).unwrap_or_else(|| self.clock.system_time()),
+                    attempts: pending.map(|post| post.attempts).unwrap_or(1),
+                })
+            },
+            None => None,
+        };
+        let state = BrokerState {
+            failed_post,
+            correlations: self.correlations.lock().unwrap().snapshot(self.clock.now()),
+        };
+        state.save(path)
+    }
+
+    // a failed post that was saved replaces the one kept here, the slate ids are merged
+    pub fn load_state(&self, path: &Path) -> Result<(), Error> {
+        let state = BrokerState::load(path)?;
+        if let Some(post) = state.failed_post {
+            let mut to = GrinboxAddress::from_str(&post.recipient)?;
+            if post.port.is_some() {
+                to.port = post.port;
+            }
+            let slate_id = post.slate.id.to_string();
+            self.pending_posts.lock().unwrap().insert(slate_id.clone(), PendingPost {
+                slate_id,
+                recipient: to.stripped(),
+                enqueued_at: post.enqueued_at,
+                attempts: post.attempts,
+            });
+            *self.failed_post.lock().unwrap() = Some((post.slate, to, post.memo, post.guarantee));
+        }
+        self.correlations.lock().unwrap().restore(state.correlations, self.clock.now());
+        Ok(())
+    }
+
+    // `memo` goes along in plain text, even when the slate itself is encrypted
+    pub fn post_slate_with_memo(&self, slate: &Slate, to: &Address, memo: &str, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        if memo.chars().count() > self.config.max_memo_length {
+            Err(Wallet713Error::MemoTooLong(self.config.max_memo_length))?;
+        }
+        self.post(slate, to, Some(memo), guarantee)
+    }
+
+    // also shares the slate ids seen, so the subscriber recognizes answers to our posts
+    pub fn set_local_subscriber(&mut self, subscriber: &GrinboxSubscriber) {
+        self.local_subscriber = Some(subscriber.broker.clone());
+        self.correlations = subscriber.broker.correlations.clone();
+    }
+
+    fn throttle(&self) -> Result<(), Error> {
+        let rate_limiter = match self.rate_limiter {
+            Some(ref rate_limiter) => rate_limiter,
+            None => return Ok(()),
+        };
+        loop {
+            let wait = match rate_limiter.lock().unwrap().try_take(self.clock.now()) {
+                Ok(_) => return Ok(()),
+                Err(wait) => wait,
+            };
+            match self.config.rate_limit_policy {
+                RateLimitPolicy::Reject => Err(Wallet713Error::RateLimited)?,
+                RateLimitPolicy::Delay => self.clock.sleep(wait),
+            }
+        }
+    }
+
+    fn post_slate_locally(&self, slate: &Slate, to: &GrinboxAddress, memo: Option<&str>) -> bool {
+        if self.config.self_send_policy != SelfSendPolicy::Loopback || !same_relay_address(&self.config, to, &self.address) {
+            return false;
+        }
+        match self.local_subscriber {
+            Some(ref broker) => broker.deliver_locally(slate, &self.address, memo),
+            None => false,
+        }
+    }
+
+    fn audit(&self, slate: &Slate, to: &GrinboxAddress, loopback: bool, guarantee: DeliveryGuarantee) {
+        if let Some(ref auditor) = self.auditor {
+            auditor.on_posted(&PostRecord {
+                slate_id: slate.id.to_string(),
+                recipient: to.stripped(),
+                encrypted: self.config.use_encryption && !loopback,
+                loopback,
+                guarantee,
+            });
+        }
+    }
+
+    fn try_post_slate(&self, slate: &Slate, to: &GrinboxAddress, memo: Option<&str>, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        let mut broker = GrinboxBroker::new(self.config.clone())?;
+        broker.signer = self.signer.clone();
+        broker.clock = self.clock.clone();
+        if let Some(ref codec) = self.codec {
+            broker.codec = codec.clone();
+        }
+        if let Some(max_version) = self.recipient_slate_versions.lock().unwrap().get(&to.stripped()) {
+            broker.codec = Arc::new(DowngradingCodec::new(broker.codec.clone(), *max_version));
+        }
+        if self.post_slate_locally(slate, to, memo) {
+            self.audit(slate, to, true, guarantee);
+            return Ok(None);
+        }
+        self.throttle()?;
+        if guarantee == DeliveryGuarantee::FireAndForget {
+            if let Some(ref subscriber) = self.local_subscriber {
+                if subscriber.post_slate_over_subscription(slate, to, &self.address, &self.secret_key, &broker.signer(&self.secret_key), &broker.codec, memo)? {
+                    subscriber.expect_sender(slate, to);
+                    self.correlations.lock().unwrap().record_outgoing(&slate.id.to_string(), &to.stripped(), self.clock.now());
+                    self.audit(slate, to, false, guarantee);
+                    return Ok(None);
+                }
+            }
+        }
+        let id = broker.post_slate(slate, to, &self.address, &self.secret_key, memo, guarantee)?;
+        if let Some(ref subscriber) = self.local_subscriber {
+            subscriber.expect_sender(slate, to);
+        }
+        self.correlations.lock().unwrap().record_outgoing(&slate.id.to_string(), &to.stripped(), self.clock.now());
+        self.audit(slate, to, false, guarantee);
+        Ok(id)
+    }
+
+    fn post(&self, slate: &Slate, to: &Address, memo: Option<&str>, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        let to = GrinboxAddress::from_address(to)?;
+        let slate_id = slate.id.to_string();
+        self.pending_posts.lock().unwrap().entry(slate_id.clone())
+            .or_insert_with(|| PendingPost {
+                slate_id: slate_id.clone(),
+                recipient: to.stripped(),
+                enqueued_at: self.clock.system_time(),
+                attempts: 0,
+            })
+            .attempts += 1;
+        let result = self.try_post_slate(slate, &to, memo, guarantee);
+        let remember = self.config.remember_failed_post && result.is_err();
+        if self.config.remember_failed_post {
+            let mut failed_post = self.failed_post.lock().unwrap();
+            // only one failed post is kept for a retry, an older one is not pending anymore then
+            if let Some((ref previous, _, _, _)) = *failed_post {
+                if previous.id.to_string() != slate_id {
+                    self.pending_posts.lock().unwrap().remove(&previous.id.to_string());
+                }
+            }
+            *failed_post = match result {
+                Ok(_) => None,
+                Err(_) => Some((slate.clone(), to, memo.map(|memo| memo.to_string()), guarantee)),
+            };
+        }
+        if !remember {
+            self.pending_posts.lock().unwrap().remove(&slate_id);
+        }
+        result
+    }
+}
+
+// the cli does not reach these, they are kept for tests
+#[cfg(test)]
+impl GrinboxPublisher {
+    // uses the key derived for `index` instead of one shared by all addresses
+    pub fn with_derived_key(keys: &AddressKeySource, index: u32, domain: &str, port: Option<u16>, config: BrokerConfig) -> Result<Self, Error> {
+        let (address, secret_key) = derived_address(keys, index, domain, port)?;
+        Self::with_config(&address, &secret_key, config)
+    }
+
+    pub fn address(&self) -> &GrinboxAddress {
+        &self.address
+    }
+
+    pub fn config(&self) -> BrokerConfig {
+        self.config.clone()
+    }
+
+    pub fn set_challenge_signer(&mut self, signer: SharedSigner) {
+        self.signer = Some(signer);
+    }
+
+    pub fn set_slate_codec(&mut self, codec: SharedCodec) {
+        self.codec = Some(codec);
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    // posts on a background thread, so callers like the cli stay responsive while a slow relay
+    // is contacted
+    pub fn post_slate_async(&self, slate: &Slate, to: &Address, guarantee: DeliveryGuarantee) -> JoinHandle<Result<Option<String>, Error>> {
+        let publisher = self.clone();
+        let slate = slate.clone();
+        let to = GrinboxAddress::from_address(to);
+        thread::spawn(move || {
+            publisher.post_slate_with_guarantee(&slate, &to?, guarantee)
         })
     }
+
+    pub fn set_address_resolver(&mut self, resolver: Arc<AddressResolver>) {
+        self.resolver = resolver;
+    }
+
+    // told about every slate that was posted successfully
+    pub fn set_post_auditor(&mut self, auditor: Arc<PostAuditor>) {
+        self.auditor = Some(auditor);
+    }
+
+    // posts the slate and waits for `to` to send it back, e.g. signed. this subscribes with the
+    // address of the publisher for as long as it waits, so it should not be used while another
+    // subscriber is listening on that address.
+    pub fn send_and_await_response(&self, slate: &Slate, to: &Address, timeout: Duration) -> Result<Slate, Error> {
+        let to = GrinboxAddress::from_address(to)?;
+        let mut subscriber = GrinboxSubscriber::with_config(&self.address, &self.secret_key, self.config.clone())?;
+        subscriber.broker.signer = self.signer.clone();
+        subscriber.broker.clock = self.clock.clone();
+        if let Some(ref codec) = self.codec {
+            subscriber.broker.codec = codec.clone();
+        }
+        subscriber.broker.expect_sender(slate, &to);
+
+        let (sender, receiver) = channel();
+        subscriber.start(Box::new(ResponseHandler {
+            slate_id: slate.id.to_string(),
+            from: to.stripped(),
+            sender,
+        }))?;
+        // `recv_timeout` waits in real time, so the deadline has to be kept in real time too
+        let start = Instant::now();
+        let mut posted = false;
+        let result = loop {
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) => remaining,
+                None => break Err(Wallet713Error::ReceiveTimeout.into()),
+            };
+            match receiver.recv_timeout(remaining) {
+                // only post once we are listening, so a quick answer can not be missed
+                Ok(Ok(None)) => if !posted {
+                    posted = true;
+                    if let Err(e) = self.post_slate(slate, &to) {
+                        break Err(e);
+                    }
+                },
+                Ok(Ok(Some(response))) => break Ok(response),
+                Ok(Err(e)) => break Err(e),
+                Err(RecvTimeoutError::Timeout) => break Err(Wallet713Error::ReceiveTimeout.into()),
+                Err(RecvTimeoutError::Disconnected) => break Err(Wallet713Error::GrinboxWebsocketAbnormalTermination.into()),
+            }
+        };
+        subscriber.stop();
+        result
+    }
+
+    pub fn correlations(&self) -> SharedCorrelations {
+        self.correlations.clone()
+    }
 }
 
 impl Publisher for GrinboxPublisher {
-    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<(), Error> {
-        let broker = GrinboxBroker::new(self.use_encryption)?;
-        let to = GrinboxAddress::from_str(&to.to_string())?;
-        broker.post_slate(slate, &to, &self.address, &self.secret_key)?;
-        Ok(())
+    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<Option<String>, Error> {
+        self.post_slate_with_guarantee(slate, to, DeliveryGuarantee::FireAndForget)
+    }
+
+    fn post_slate_with_guarantee(&self, slate: &Slate, to: &Address, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        self.post(slate, to, None, guarantee)
     }
 }
 
@@ -50,276 +443,2404 @@ pub struct GrinboxSubscriber {
 }
 
 impl GrinboxSubscriber {
-    pub fn new(address: &GrinboxAddress, secret_key: &SecretKey, use_encryption: bool) -> Result<Self, Error> {
+    pub fn with_config(address: &GrinboxAddress, secret_key: &SecretKey, config: BrokerConfig) -> Result<Self, Error> {
         Ok(Self {
             address: address.clone(),
-            broker: GrinboxBroker::new(use_encryption)?,
+            broker: GrinboxBroker::new(config)?,
             secret_key: secret_key.clone(),
         })
     }
+
+    // for handlers that wait on their own, like `WebhookHandler::with_clock`
+    pub fn clock(&self) -> SharedClock {
+        self.broker.clock.clone()
+    }
+
+    pub fn set_sender_filter(&mut self, filter: SenderFilter) {
+        self.broker.sender_filter = Some(Arc::new(filter));
+    }
+
+    // why the last subscription ended, or `None` while one is running
+    pub fn last_close_reason(&self) -> Option<CloseReason> {
+        self.broker.last_close_reason()
+    }
+
+    pub fn status(&self) -> BrokerStatus {
+        self.broker.status()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.broker.connection_state()
+    }
+
+    // lets the slates that already arrived be processed before closing, for up to `timeout`.
+    // returns false if the handler was still busy when the time ran out.
+    pub fn stop_graceful(&self, timeout: Duration) -> bool {
+        self.broker.stop_graceful(timeout)
+    }
+
+    // subscribes just long enough to get a single slate, for scripts that want one and exit
+    pub fn receive_one(&mut self, timeout: Duration) -> Result<(GrinboxAddress, Slate), Error> {
+        let (sender, receiver) = channel();
+        self.start(Box::new(ReceiveOneHandler { sender }))?;
+        let result = receiver.recv_timeout(timeout);
+        self.stop();
+        match result {
+            Ok(received) => received,
+            Err(RecvTimeoutError::Timeout) => Err(Wallet713Error::ReceiveTimeout.into()),
+            Err(RecvTimeoutError::Disconnected) => Err(Wallet713Error::GrinboxWebsocketAbnormalTermination.into()),
+        }
+    }
 }
 
-impl Subscriber for GrinboxSubscriber {
-    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<(), Error> {
-        self.broker.subscribe(&self.address, &self.secret_key, handler)?;
+// the cli does not reach these, they are kept for tests
+#[cfg(test)]
+impl GrinboxSubscriber {
+    pub fn with_derived_key(keys: &AddressKeySource, index: u32, domain: &str, port: Option<u16>, config: BrokerConfig) -> Result<Self, Error> {
+        let (address, secret_key) = derived_address(keys, index, domain, port)?;
+        Self::with_config(&address, &secret_key, config)
+    }
+
+    pub fn address(&self) -> &GrinboxAddress {
+        &self.address
+    }
+
+    pub fn config(&self) -> BrokerConfig {
+        self.broker.config()
+    }
+
+    pub fn set_challenge_signer(&mut self, signer: SharedSigner) {
+        self.broker.signer = Some(signer);
+    }
+
+    pub fn set_slate_codec(&mut self, codec: SharedCodec) {
+        self.broker.codec = codec;
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.broker.clock = clock;
+    }
+
+    pub fn set_slate_transform(&mut self, transform: SharedTransform) {
+        self.broker.transform = Some(transform);
+    }
+
+    pub fn supports_capability(&self, capability: &str) -> bool {
+        self.broker.supports_capability(capability)
+    }
+
+    // switches to the address of `new_key`. a running subscription subscribes the new address on
+    // the same connection before it lets go of the old one, so nothing sent in between is lost.
+    // a custom challenge signer is replaced by one for the new key.
+    pub fn rotate_key(&mut self, new_key: SecretKey) -> Result<(), Error> {
+        let address = GrinboxAddress::from_secret_key(&new_key, self.address.domain.clone(), self.address.port)?;
+        let signer: SharedSigner = Arc::new(KeySigner::new(&new_key));
+        if self.broker.is_running() {
+            self.broker.rotate_key((address.clone(), new_key.clone(), signer.clone()))?;
+        }
+        self.broker.signer = Some(signer);
+        self.address = address;
+        self.secret_key = new_key;
         Ok(())
     }
 
-    fn stop(&self) {
-        self.broker.stop();
+    // only decrypts what `from` sent, nothing is verified or handed to a handler. meant for
+    // finding out why slates from someone can not be read.
+    pub fn try_decrypt(&self, raw: &str, from: &GrinboxAddress) -> Result<String, Error> {
+        decrypt_slate(raw, from, &self.address, self.broker.config.require_bound_slates, &self.secret_key)
     }
 
-    fn is_running(&self) -> bool {
-        self.broker.is_running()
+    // the slate ids this subscriber has seen, and those its publisher sent
+    pub fn correlations(&self) -> SharedCorrelations {
+        self.broker.correlations.clone()
+    }
+
+    // subscribes without any wallet behind it, every slate is only passed to `observer`. handy
+    // to see whether slates arrive at all.
+    pub fn observe(&mut self, observer: SlateObserver) -> Result<Subscription, Error> {
+        self.start(Box::new(ObserverHandler { observer }))
+    }
+
+    // subscribes and hands every verified slate to the returned channel instead of a handler.
+    // the channel is closed once the subscription is, whether stopped or ended by an error.
+    pub fn receiver(&mut self) -> Result<Receiver<(GrinboxAddress, Slate)>, Error> {
+        let (sender, receiver) = channel();
+        self.start(Box::new(ChannelHandler { sender: Mutex::new(Some(sender)) }))?;
+        Ok(receiver)
     }
 }
 
-#[derive(Clone)]
-struct GrinboxBroker {
-    inner: Arc<Mutex<Option<Sender>>>,
-    use_encryption: bool,
+struct ReceiveOneHandler {
+    sender: ChannelSender<Result<(GrinboxAddress, Slate), Error>>,
 }
 
-impl GrinboxBroker {
-    fn new(use_encryption: bool) -> Result<Self, Error> {
-        Ok(Self {
-            inner: Arc::new(Mutex::new(None)),
-            use_encryption,
-        })
+impl SubscriptionHandler for ReceiveOneHandler {
+    fn on_open(&self) {}
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        let received = GrinboxAddress::from_address(from).map(|from| (from, slate.clone()));
+        self.sender.send(received).is_ok();
     }
 
-    fn post_slate(&self, slate: &Slate, to: &GrinboxAddress, from: &GrinboxAddress, secret_key: &SecretKey) -> Result<(), Error> {
-        let url = {
-            let to = to.clone();
-            format!("wss://{}:{}", to.domain, to.port.unwrap_or(DEFAULT_GRINBOX_PORT))
-        };
-        let pkey = to.public_key()?;
-        let skey = secret_key.clone();
-        connect(url, move |sender| {
-            move |msg: Message| {
-                let response = serde_json::from_str::<ProtocolResponse>(&msg.to_string()).expect("could not parse response!");
-                match response {
-                    ProtocolResponse::Challenge { str } => {
-                        let slate_str = match self.use_encryption {
-                            true => {
-                                let message = EncryptedMessage::new(serde_json::to_string(&slate).unwrap(), &pkey, &skey).map_err(|_|
-                                    WsError::new(WsErrorKind::Protocol, "could not encrypt slate!")
-                                )?;
-                                serde_json::to_string(&message).unwrap()
-                            },
-                            false => serde_json::to_string(&slate).unwrap(),
-                        };
-
-                        let mut challenge = String::new();
-                        challenge.push_str(&slate_str);
-                        challenge.push_str(&str);
-                        let signature = GrinboxClient::generate_signature(&challenge, secret_key);
-                        let request = ProtocolRequest::PostSlate {
-                            from: from.stripped(),
-                            to: to.public_key.clone(),
-                            str: slate_str,
-                            signature,
-                        };
-                        sender.send(serde_json::to_string(&request).unwrap()).unwrap();
-                        sender.close(CloseCode::Normal).is_ok();
-                    },
-                    _ => {}
-                }
-                Ok(())
-            }
-        })?;
-        Ok(())
+    fn on_close(&self, result: CloseReason) {
+        if let Err(e) = result.into_result() {
+            self.sender.send(Err(e)).is_ok();
+        }
     }
 
-    fn subscribe(&mut self, address: &GrinboxAddress, secret_key: &SecretKey, handler: Box<SubscriptionHandler + Send>) -> Result<(), Error> {
-        let handler = Arc::new(Mutex::new(handler));
-        let url = {
-            let cloned_address = address.clone();
-            format!("wss://{}:{}", cloned_address.domain, cloned_address.port.unwrap_or(DEFAULT_GRINBOX_PORT))
+    fn on_dropped(&self) {}
+
+    fn on_reestablished(&self) {}
+}
+
+#[cfg(test)]
+struct ChannelHandler {
+    sender: Mutex<Option<ChannelSender<(GrinboxAddress, Slate)>>>,
+}
+
+#[cfg(test)]
+impl SubscriptionHandler for ChannelHandler {
+    fn on_open(&self) {}
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        let from = match GrinboxAddress::from_address(from) {
+            Ok(from) => from,
+            Err(e) => {
+                error!("dropping slate [{}] from {}: {}", slate.id, from.stripped(), e);
+                return;
+            },
         };
-        let secret_key = secret_key.clone();
-        let cloned_address = address.clone();
-        let cloned_inner = self.inner.clone();
-        let cloned_handler = handler.clone();
-        let use_encryption = self.use_encryption;
-        thread::spawn(move || {
-            let cloned_cloned_inner = cloned_inner.clone();
-            let result = connect(url, move |sender| {
-                if let Ok(mut guard) = cloned_cloned_inner.lock() {
-                    *guard = Some(sender.clone());
-                };
+        if let Some(ref sender) = *self.sender.lock().unwrap() {
+            sender.send((from, slate.clone())).is_ok();
+        }
+    }
 
-                let client = GrinboxClient {
-                    sender,
-                    handler: cloned_handler.clone(),
-                    challenge: None,
-                    address: cloned_address.clone(),
-                    secret_key,
-                    use_encryption,
-                };
-                client
-            });
+    // dropping the sender is what closes the channel
+    fn on_close(&self, result: CloseReason) {
+        if let Err(e) = result.into_result() {
+            error!("subscription ended: {}", e);
+        }
+        self.sender.lock().unwrap().take();
+    }
 
-            if let Ok(mut guard) = cloned_inner.lock() {
-                *guard = None;
-            };
+    fn on_dropped(&self) {}
 
-            match result {
-                Err(_) => handler.lock().unwrap().on_close(CloseReason::Abnormal(Error::from(Wallet713Error::GrinboxWebsocketAbnormalTermination))),
-                _ => handler.lock().unwrap().on_close(CloseReason::Normal),
-            }
-        });
-        Ok(())
+    fn on_reestablished(&self) {}
+}
+
+// waits for a single slate to come back from whoever it was sent to. `None` reports the
+// subscription is open.
+#[cfg(test)]
+struct ResponseHandler {
+    slate_id: String,
+    from: String,
+    sender: ChannelSender<Result<Option<Slate>, Error>>,
+}
+
+#[cfg(test)]
+impl SubscriptionHandler for ResponseHandler {
+    fn on_open(&self) {
+        self.sender.send(Ok(None)).is_ok();
     }
 
-    fn stop(&self) {
-        let mut guard = self.inner.lock().unwrap();
-        if let Some(ref sender) = *guard {
-            sender.close(CloseCode::Normal).is_ok();
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        if slate.id.to_string() != self.slate_id || from.stripped() != self.from {
+            debug!("ignoring slate [{}] from {} while waiting for [{}]", slate.id, from.stripped(), self.slate_id);
+            return;
         }
-        *guard = None;
+        self.sender.send(Ok(Some(slate.clone()))).is_ok();
     }
 
-    fn is_running(&self) -> bool {
-        let guard = self.inner.lock().unwrap();
-        guard.is_some()
+    fn on_close(&self, result: CloseReason) {
+        if let Err(e) = result.into_result() {
+            self.sender.send(Err(e)).is_ok();
+        }
     }
+
+    fn on_dropped(&self) {}
+
+    fn on_reestablished(&self) {}
 }
 
-struct GrinboxClient {
-    sender: Sender,
-    handler: Arc<Mutex<Box<SubscriptionHandler + Send>>>,
-    challenge: Option<String>,
-    address: GrinboxAddress,
-    secret_key: SecretKey,
-    use_encryption: bool,
+// only logs what arrives and shows it to the observer, nothing is ever answered
+#[cfg(test)]
+struct ObserverHandler {
+    observer: SlateObserver,
 }
 
-impl GrinboxClient {
-    fn generate_signature(challenge: &str, secret_key: &SecretKey) -> String {
-        let signature = sign_challenge(challenge, secret_key).expect("could not sign challenge!");
-        signature.to_hex()
+#[cfg(test)]
+impl SubscriptionHandler for ObserverHandler {
+    fn on_open(&self) {
+        info!("observing subscription opened");
     }
 
-    fn subscribe(&self, challenge: &str) -> Result<(), Error> {
-        let signature = GrinboxClient::generate_signature(challenge, &self.secret_key);
-        let request = ProtocolRequest::Subscribe { address: self.address.public_key.to_string(), signature };
-        self.send(&request).expect("could not send subscribe request!");
-        Ok(())
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        self.on_slate_with_meta(from, slate, &SlateMeta::default());
     }
 
-    fn verify_slate_signature(&self, from: &str, str: &str, challenge: &str, signature: &str) -> Result<(), Error> {
-        let from = GrinboxAddress::from_str(from)?;
-        let public_key = from.public_key()?;
-        let signature = Signature::from_hex(signature)?;
-        let mut challenge_builder = String::new();
-        challenge_builder.push_str(str);
-        challenge_builder.push_str(challenge);
-        verify_signature(&challenge_builder, &signature, &public_key)?;
-        Ok(())
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, meta: &SlateMeta) {
+        let from = match GrinboxAddress::from_address(from) {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("could not parse address `{}`: {}", from, e);
+                return;
+            },
+        };
+        info!("observed slate [{}] from {} with {} participants", slate.id, from.stripped(), slate.participant_data.len());
+        (self.observer)(&from, slate, meta);
     }
 
-    fn send(&self, request: &ProtocolRequest) -> Result<(), Error> {
-        let request = serde_json::to_string(&request).unwrap();
-        self.sender.send(request)?;
-        Ok(())
+    fn on_close(&self, result: CloseReason) {
+        match result.into_result() {
+            Ok(()) => info!("observing subscription closed"),
+            Err(e) => warn!("observing subscription closed: {}", e),
+        }
+    }
+
+    fn on_dropped(&self) {
+        info!("observing subscription dropped");
+    }
+
+    fn on_reestablished(&self) {
+        info!("observing subscription reestablished");
     }
 }
 
-impl Handler for GrinboxClient {
-    fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
-        self.handler.lock().unwrap().on_open();
-        try!(self.sender.timeout(KEEPALIVE_INTERVAL_MS, KEEPALIVE_TOKEN));
-        Ok(())
+impl Subscriber for GrinboxSubscriber {
+    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<Subscription, Error> {
+        let (subscription, handler) = Subscription::watch(Box::new(self.clone()), handler);
+        self.broker.subscribe(&self.address, &self.secret_key, handler)?;
+        Ok(subscription)
     }
 
-    fn on_timeout(&mut self, event: Token) -> WsResult<()> {
-        match event {
-            KEEPALIVE_TOKEN => {
-                self.sender.ping(vec![])?;
-                self.sender.timeout(KEEPALIVE_INTERVAL_MS, KEEPALIVE_TOKEN)
+    fn stop(&self) {
+        self.broker.stop();
+    }
+
+    fn is_running(&self) -> bool {
+        self.broker.is_running()
+    }
+
+    fn status(&self) -> BrokerStatus {
+        self.broker.status()
+    }
+}
+
+// keeps what the relay advertised on previous connections so that a downgrade after a
+// reconnect can be detected and reported instead of silently breaking dependent features
+#[derive(Default)]
+struct RelayCapabilities {
+    advertised: Option<Vec<String>>,
+    known: Vec<String>,
+    policy: Option<CapabilityDowngradePolicy>,
+}
+
+impl RelayCapabilities {
+    fn update(&mut self, capabilities: Vec<String>, policy: CapabilityDowngradePolicy) -> Vec<String> {
+        let lost: Vec<String> = match self.advertised {
+            Some(ref previous) => previous.iter()
+                .filter(|c| !capabilities.contains(c))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        for capability in &capabilities {
+            if !self.known.contains(capability) {
+                self.known.push(capability.clone());
             }
-            _ => Err(WsError::new(WsErrorKind::Internal, "Invalid timeout token encountered!")),
         }
+        self.advertised = Some(capabilities);
+        self.policy = Some(policy);
+        lost
     }
 
-
-    fn on_message(&mut self, msg: Message) -> WsResult<()> {
-        let response = serde_json::from_str::<ProtocolResponse>(&msg.to_string()).map_err(|_| {
-            WsError::new(WsErrorKind::Protocol, "could not parse response!")
-        })?;
-        match response {
-            ProtocolResponse::Challenge { str } => {
-                self.challenge = Some(str.clone());
-                self.subscribe(&str).map_err(|_| {
-                    WsError::new(WsErrorKind::Protocol, "error attempting to subscribe!")
-                })?;
+    fn supports(&self, capability: &str) -> bool {
+        match self.policy {
+            Some(CapabilityDowngradePolicy::KeepEnabled) => self.known.iter().any(|c| c == capability),
+            _ => match self.advertised {
+                Some(ref advertised) => advertised.iter().any(|c| c == capability),
+                None => false,
             },
-            ProtocolResponse::Slate { from, str, challenge, signature } => {
-                if let Ok(_) = self.verify_slate_signature(&from, &str, &challenge, &signature) {
+        }
+    }
+}
 
-                    let from = match GrinboxAddress::from_str(&from) {
+#[derive(Clone)]
+struct GrinboxBroker {
+    inner: Arc<Mutex<Option<Sender>>>,
+    config: BrokerConfig,
+    capabilities: Arc<Mutex<RelayCapabilities>>,
+    handler: Arc<Mutex<Option<SharedHandler>>>,
+    connections: Arc<Mutex<u32>>,
+    signer: Option<SharedSigner>,
+    codec: SharedCodec,
+    clock: SharedClock,
+    stop_signal: Arc<Mutex<bool>>,
+    relay_url: Arc<Mutex<Option<String>>>,
+    challenge: Arc<Mutex<Option<String>>>,
+    subscription: Arc<Mutex<Option<(String, SharedSigner)>>>,
+    last_close_reason: Arc<Mutex<Option<CloseReason>>>,
+    status: Arc<Mutex<BrokerStatus>>,
+    // who we expect to answer each slate we sent, by slate id
+    expected_senders: Arc<Mutex<HashMap<String, String>>>,
+    // replaces the key the subscription started with, once it was rotated
+    identity: Arc<Mutex<Option<Identity>>>,
+    correlations: SharedCorrelations,
+    transform: Option<SharedTransform>,
+    public_keys: SharedKeyCache,
+    sender_filter: Option<SharedSenderFilter>,
+    post_slots: Arc<PostSlots>,
+    // shared by clones of this broker but not by its connection threads
+    owners: Arc<()>,
+}
+
+impl GrinboxBroker {
+    fn new(config: BrokerConfig) -> Result<Self, Error> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(None)),
+            codec: Arc::new(JsonCodec::new(config.target_slate_version).with_pretty_printing(config.pretty_slates)),
+            clock: Arc::new(SystemClock),
+            correlations: Arc::new(Mutex::new(CorrelationStore::new(Duration::from_millis(config.correlation_ttl_ms)))),
+            public_keys: Arc::new(Mutex::new(PublicKeyCache::new(config.public_key_cache_size))),
+            post_slots: Arc::new(PostSlots::new(config.max_post_connections)),
+            config,
+            capabilities: Arc::new(Mutex::new(RelayCapabilities::default())),
+            handler: Arc::new(Mutex::new(None)),
+            connections: Arc::new(Mutex::new(0)),
+            signer: None,
+            stop_signal: Arc::new(Mutex::new(false)),
+            relay_url: Arc::new(Mutex::new(None)),
+            challenge: Arc::new(Mutex::new(None)),
+            subscription: Arc::new(Mutex::new(None)),
+            last_close_reason: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(BrokerStatus::default())),
+            expected_senders: Arc::new(Mutex::new(HashMap::new())),
+            identity: Arc::new(Mutex::new(None)),
+            transform: None,
+            sender_filter: None,
+            owners: Arc::new(()),
+        })
+    }
+
+    #[cfg(test)]
+    fn config(&self) -> BrokerConfig {
+        self.config.clone()
+    }
+
+    fn supports_capability(&self, capability: &str) -> bool {
+        self.capabilities.lock().unwrap().supports(capability)
+    }
+
+    fn last_close_reason(&self) -> Option<CloseReason> {
+        self.last_close_reason.lock().unwrap().clone()
+    }
+
+    fn status(&self) -> BrokerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // the subscription is kept from `subscribe` until its thread gave up or was stopped
+    fn connection_state(&self) -> ConnectionState {
+        if *self.stop_signal.lock().unwrap() || self.subscription.lock().unwrap().is_none() {
+            return ConnectionState::Stopped;
+        }
+        match self.status.lock().unwrap().connected_since {
+            Some(_) => ConnectionState::Connected,
+            None => ConnectionState::Connecting,
+        }
+    }
+
+    // only `sender` may answer the slate from now on
+    fn expect_sender(&self, slate: &Slate, sender: &GrinboxAddress) {
+        self.expected_senders.lock().unwrap().insert(slate.id.to_string(), sender.stripped());
+    }
+
+    // hands the slate straight to our own handler. if the handler is busy (e.g. it is the one
+    // posting) we return false so the caller goes through the relay instead of deadlocking.
+    fn deliver_locally(&self, slate: &Slate, from: &GrinboxAddress, memo: Option<&str>) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        let handler = match *self.handler.lock().unwrap() {
+            Some(ref handler) => handler.clone(),
+            None => return false,
+        };
+        if !accepts_sender(&self.sender_filter, from) {
+            // it was delivered, just not taken
+            return true;
+        }
+        let result = match handler.try_lock() {
+            Ok(handler) => {
+                let mut slate = match self.transform {
+                    Some(ref transform) => match transform.transform(from, slate.clone()) {
                         Ok(x) => x,
-                        Err(_) => {
-                            cli_message!("could not parse address!");
-                            return Ok(());
+                        Err(e) => {
+                            warn!("slate from {} was rejected by the transform: {}", from, e);
+                            // it was delivered, just not taken
+                            return true;
                         },
-                    };
+                    },
+                    None => slate.clone(),
+                };
+                let meta = SlateMeta {
+                    recipient: Some(from.to_string()),
+                    memo: memo.map(|memo| memo.to_string()),
+                    ..SlateMeta::default()
+                };
+                handler.on_slate_with_meta(from, &mut slate, &meta);
+                true
+            },
+            Err(_) => false,
+        };
+        result
+    }
+
+    fn signer(&self, secret_key: &SecretKey) -> SharedSigner {
+        match self.signer {
+            Some(ref signer) => signer.clone(),
+            None => Arc::new(KeySigner::new(secret_key)),
+        }
+    }
+
+    fn tls_connector(&self) -> TlsConnector {
+        TlsConnector::new(self.config.pinned_certificate.clone(), self.config.ca_bundle.clone(), self.config.min_tls_version)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.connect_timeout_ms)
+    }
+
+    fn delivery_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.delivery_timeout_ms)
+    }
+
+    fn url(&self, address: &GrinboxAddress) -> String {
+        format!("wss://{}:{}", address.domain, relay_port(&self.config, address))
+    }
 
-                    let mut slate: Slate = match self.use_encryption {
-                        true => {
-                            let encrypted_message: EncryptedMessage = match serde_json::from_str(&str) {
-                                Ok(x) => x,
-                                Err(_) => {
-                                    cli_message!("could not parse encrypted message!");
-                                    return Ok(());
-                                },
-                            };
-                            let pkey = match from.public_key() {
-                                Ok(x) => x,
-                                Err(_) => {
-                                    cli_message!("could not parse public key!");
-                                    return Ok(());
-                                },
-                            };
-
-                            let decrypted_message = match encrypted_message.decrypt(&pkey, &self.secret_key) {
-                                Ok(x) => x,
-                                Err(_) => {
-                                    cli_message!("could not decrypt message!");
-                                    return Ok(());
-                                },
-                            };
-
-                            let slate: Slate = match serde_json::from_str(&decrypted_message) {
-                                Ok(x) => x,
-                                Err(_) => {
-                                    cli_message!("could not parse slate!");
-                                    return Ok(());
-                                },
-                            };
-
-                            slate
+    // the relay of the address first, then the configured fallbacks
+    fn relay_urls(&self, address: &GrinboxAddress) -> Vec<String> {
+        let mut urls = vec![self.url(address)];
+        for relay in &self.config.fallback_relays {
+            let url = fallback_url(&self.config, relay);
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls
+    }
+
+    fn ping_relay(&self, address: &GrinboxAddress) -> Result<Duration, Error> {
+        address.validate()?;
+        let url = self.url(address);
+        let elapsed = Arc::new(Mutex::new(None));
+        let cloned_elapsed = elapsed.clone();
+        let clock = self.clock.clone();
+        let start = clock.now();
+        let tls = self.tls_connector();
+        let cloned_tls = tls.clone();
+        let opened = Arc::new(Mutex::new(false));
+        let cloned_opened = opened.clone();
+        connect(&url, self.connect_timeout(), &self.config, self.clock.clone(), opened, move |sender| {
+            let cloned_elapsed = cloned_elapsed.clone();
+            let clock = clock.clone();
+            TlsHandler {
+                tls: cloned_tls.clone(),
+                opened: cloned_opened.clone(),
+                handler: move |msg: Message| {
+                    if let Ok(ProtocolResponse::Challenge { .. }) = serde_json::from_str::<ProtocolResponse>(&msg.to_string()) {
+                        *cloned_elapsed.lock().unwrap() = Some(clock.now() - start);
+                        sender.close(CloseCode::Normal)?;
+                    }
+                    Ok(())
+                },
+            }
+        })?;
+        if let Some(e) = tls.take_error() {
+            return Err(e);
+        }
+        let elapsed = elapsed.lock().unwrap().take();
+        elapsed.ok_or_else(|| Wallet713Error::GrinboxWebsocketAbnormalTermination.into())
+    }
+
+    // asks the relay of `address` whether it currently has a subscription for it
+    fn query_presence(&self, address: &GrinboxAddress) -> Result<(bool, Vec<u64>), Error> {
+        address.validate()?;
+        let url = self.url(address);
+        let online = Arc::new(Mutex::new(None));
+        let cloned_online = online.clone();
+        let public_key = address.public_key.clone();
+        let tls = self.tls_connector();
+        let cloned_tls = tls.clone();
+        let opened = Arc::new(Mutex::new(false));
+        let cloned_opened = opened.clone();
+        let wire = self.config.log_wire;
+        connect(&url, self.connect_timeout(), &self.config, self.clock.clone(), opened, move |sender| {
+            let cloned_online = cloned_online.clone();
+            let public_key = public_key.clone();
+            TlsHandler {
+                tls: cloned_tls.clone(),
+                opened: cloned_opened.clone(),
+                handler: move |msg: Message| {
+                    log_wire(wire, "<", &msg.to_string());
+                    match serde_json::from_str::<ProtocolResponse>(&msg.to_string()) {
+                        Ok(ProtocolResponse::Challenge { .. }) => {
+                            let request = serde_json::to_string(&ProtocolRequest::IsOnline { address: public_key.clone() }).map_err(|e|
+                                WsError::new(WsErrorKind::Protocol, format!("could not serialize request: {}", e))
+                            )?;
+                            log_wire(wire, ">", &request);
+                            sender.send(request)?;
                         },
-                        false => match serde_json::from_str(&str) {
+                        Ok(ProtocolResponse::Presence { online, slate_versions }) => {
+                            *cloned_online.lock().unwrap() = Some((online, slate_versions));
+                            sender.close(CloseCode::Normal)?;
+                        },
+                        Ok(response @ ProtocolResponse::Error { .. }) => {
+                            error!("{}", response);
+                            sender.close(CloseCode::Normal)?;
+                        },
+                        _ => {},
+                    }
+                    Ok(())
+                },
+            }
+        })?;
+        if let Some(e) = tls.take_error() {
+            return Err(e);
+        }
+        let online = online.lock().unwrap().take();
+        online.ok_or_else(|| Wallet713Error::GrinboxWebsocketAbnormalTermination.into())
+    }
+
+    // a fire and forget post never waits for the relay, so it never learns the id of the slate
+    fn post_slate(&self, slate: &Slate, to: &GrinboxAddress, from: &GrinboxAddress, secret_key: &SecretKey, memo: Option<&str>, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        to.validate()?;
+        let urls = self.relay_urls(to);
+        // the primary relay is always in there, so there is a last one to settle on
+        let (last, fallbacks) = urls.split_last().ok_or(Wallet713Error::GrinboxWebsocketAbnormalTermination)?;
+        for (i, url) in fallbacks.iter().enumerate() {
+            let opened = Arc::new(Mutex::new(false));
+            let result = self.post_slate_via(url, opened.clone(), slate, to, from, secret_key, memo, guarantee);
+            if *opened.lock().unwrap() {
+                return result;
+            }
+            warn!("could not reach {}, trying {}", url, urls[i + 1]);
+        }
+        self.post_slate_via(last, Arc::new(Mutex::new(false)), slate, to, from, secret_key, memo, guarantee)
+    }
+
+    fn post_slate_via(&self, url: &str, opened: Arc<Mutex<bool>>, slate: &Slate, to: &GrinboxAddress, from: &GrinboxAddress, secret_key: &SecretKey, memo: Option<&str>, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        // waiting for a free connection counts against the time the post is given
+        let started = self.clock.now();
+        let _slot = PostSlots::acquire(&self.post_slots, &*self.clock, self.delivery_timeout())
+            .ok_or(Wallet713Error::PostConnectionsExhausted(self.post_slots.capacity()))?;
+        let delivery_timeout = self.delivery_timeout().checked_sub(self.clock.now() - started).unwrap_or(Duration::from_millis(0));
+        let signer = self.signer(secret_key);
+        let tls = self.tls_connector();
+        let cloned_tls = tls.clone();
+        let cloned_opened = opened.clone();
+        let confirmed = Arc::new(Mutex::new(false));
+        let cloned_confirmed = confirmed.clone();
+        let message_id = Arc::new(Mutex::new(None));
+        let cloned_message_id = message_id.clone();
+        connect(url, self.connect_timeout(), &self.config, self.clock.clone(), opened, move |sender| TlsHandler {
+            tls: cloned_tls.clone(),
+            opened: cloned_opened.clone(),
+            handler: move |msg: Message| {
+                log_wire(self.config.log_wire, "<", &msg.to_string());
+                let response = match serde_json::from_str::<ProtocolResponse>(&msg.to_string()) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        warn!("skipping unparseable message from relay: {}", e);
+                        return Ok(());
+                    },
+                };
+                match response {
+                    ProtocolResponse::Challenge { str, capabilities, .. } => {
+                        // only compress when the relay says it can hand compressed slates on
+                        let compress = capabilities.iter().any(|c| c == CAPABILITY_COMPRESSION);
+                        let bind_addresses = capabilities.iter().any(|c| c == CAPABILITY_BOUND_ENCRYPTION);
+                        let request = self.post_slate_request(slate, to, from, secret_key, &signer, &self.codec, &str, compress, bind_addresses, memo, guarantee).map_err(|e|
+                            WsError::new(WsErrorKind::Protocol, format!("could not post slate: {}", e))
+                        )?;
+                        let request = serde_json::to_string(&request).map_err(|e|
+                            WsError::new(WsErrorKind::Protocol, format!("could not serialize slate: {}", e))
+                        )?;
+                        log_wire(self.config.log_wire, ">", &request);
+                        sender.send(request)?;
+                        if guarantee == DeliveryGuarantee::FireAndForget {
+                            sender.close(CloseCode::Normal).is_ok();
+                        } else {
+                            // give up waiting for the relay once the delivery timeout passes
+                            let sender = sender.clone();
+                            let confirmed = cloned_confirmed.clone();
+                            let clock = self.clock.clone();
+                            thread::spawn(move || {
+                                clock.sleep(delivery_timeout);
+                                if !*confirmed.lock().unwrap() {
+                                    sender.close(CloseCode::Normal).is_ok();
+                                }
+                            });
+                        }
+                    },
+                    ProtocolResponse::Ok { id } if guarantee == DeliveryGuarantee::Acknowledged => {
+                        *cloned_message_id.lock().unwrap() = id;
+                        *cloned_confirmed.lock().unwrap() = true;
+                        sender.close(CloseCode::Normal).is_ok();
+                    },
+                    ProtocolResponse::Delivered { id, .. } if guarantee == DeliveryGuarantee::Delivered => {
+                        *cloned_message_id.lock().unwrap() = id;
+                        *cloned_confirmed.lock().unwrap() = true;
+                        sender.close(CloseCode::Normal).is_ok();
+                    },
+                    ProtocolResponse::Error { .. } => {
+                        error!("{}", response);
+                        sender.close(CloseCode::Normal).is_ok();
+                    },
+                    _ => {}
+                }
+                Ok(())
+            },
+        })?;
+        if let Some(e) = tls.take_error() {
+            return Err(e);
+        }
+        if guarantee != DeliveryGuarantee::FireAndForget && !*confirmed.lock().unwrap() {
+            Err(Wallet713Error::DeliveryNotConfirmed(to.stripped()))?;
+        }
+        let id = message_id.lock().unwrap().take();
+        Ok(id)
+    }
+
+    // fire and forget posts to the relay we are subscribed to go out over the subscription instead
+    // of dialing again. returns false when there is no live subscription to that relay.
+    fn post_slate_over_subscription(&self, slate: &Slate, to: &GrinboxAddress, from: &GrinboxAddress, secret_key: &SecretKey, signer: &SharedSigner, codec: &SharedCodec, memo: Option<&str>) -> Result<bool, Error> {
+        to.validate()?;
+        if self.relay_url.lock().unwrap().as_ref() != Some(&self.url(to)) {
+            return Ok(false);
+        }
+        let challenge = match *self.challenge.lock().unwrap() {
+            Some(ref challenge) => challenge.clone(),
+            None => return Ok(false),
+        };
+        let compress = self.supports_capability(CAPABILITY_COMPRESSION);
+        let bind_addresses = self.supports_capability(CAPABILITY_BOUND_ENCRYPTION);
+        let request = self.post_slate_request(slate, to, from, secret_key, signer, codec, &challenge, compress, bind_addresses, memo, DeliveryGuarantee::FireAndForget)?;
+        let request = serde_json::to_string(&request)?;
+        log_wire(self.config.log_wire, ">", &request);
+        match *self.inner.lock().unwrap() {
+            Some(ref sender) => sender.send(request)?,
+            None => return Ok(false),
+        };
+        Ok(true)
+    }
+
+    fn post_slate_request(&self, slate: &Slate, to: &GrinboxAddress, from: &GrinboxAddress, secret_key: &SecretKey, signer: &SharedSigner, codec: &SharedCodec, challenge: &str, compress: bool, bind_addresses: bool, memo: Option<&str>, guarantee: DeliveryGuarantee) -> Result<ProtocolRequest, Error> {
+        let compressed = self.config.compress_slates && compress;
+        let mut slate_str = codec.encode(slate)?;
+        if compressed {
+            slate_str = compress_slate(&slate_str)?;
+        }
+        let slate_str = match self.config.use_encryption {
+            // receivers from before addresses were bound could not read the slate at all, unless
+            // the relay says they can binding is left to the sender to turn on
+            true => {
+                let message = match bind_addresses || self.config.bind_slate_addresses {
+                    true => EncryptedMessage::new_bound(slate_str, &from.public_key, &to.public_key, &to.public_key()?, secret_key)?,
+                    false => EncryptedMessage::new(slate_str, &to.public_key()?, secret_key)?,
+                };
+                serde_json::to_string(&message)?
+            },
+            false => slate_str,
+        };
+
+        let signed = signed_slate_message(&self.config, &slate_str, memo, challenge);
+        let signature = signer.sign(&signed)?;
+        Ok(ProtocolRequest::PostSlate {
+            from: from_address(&self.config, from),
+            to: to.public_key.clone(),
+            str: slate_str,
+            signature,
+            confirm_delivery: guarantee == DeliveryGuarantee::Delivered,
+            compressed,
+            memo: memo.map(|memo| memo.to_string()),
+        })
+    }
+
+    fn subscribe(&mut self, address: &GrinboxAddress, secret_key: &SecretKey, handler: Box<SubscriptionHandler + Send>) -> Result<(), Error> {
+        // the relay would take the subscription, but nothing could ever be sent to it
+        address.public_key().map_err(|_| Wallet713Error::MissingPublicKey(address.to_string()))?;
+        address.validate()?;
+        let handler = Arc::new(Mutex::new(handler));
+        {
+            let mut guard = self.handler.lock().unwrap();
+            if guard.is_some() || self.is_running() {
+                Err(Wallet713Error::AlreadySubscribed)?;
+            }
+            *guard = Some(handler.clone());
+        }
+        let urls = self.relay_urls(address);
+        let signer = self.signer(secret_key);
+        let secret_key = secret_key.clone();
+        let cloned_address = address.clone();
+        let cloned_inner = self.inner.clone();
+        let cloned_handler = handler.clone();
+        let config = self.config.clone();
+        let capabilities = self.capabilities.clone();
+        let connections = self.connections.clone();
+        let handler_slot = self.handler.clone();
+        let tls = self.tls_connector();
+        let connect_timeout = self.connect_timeout();
+        let stop_signal = self.stop_signal.clone();
+        *stop_signal.lock().unwrap() = false;
+        *self.subscription.lock().unwrap() = Some((address.public_key.clone(), signer.clone()));
+        let relay_url = self.relay_url.clone();
+        let subscription = self.subscription.clone();
+        let challenge = self.challenge.clone();
+        *self.last_close_reason.lock().unwrap() = None;
+        let last_close_reason = self.last_close_reason.clone();
+        *self.status.lock().unwrap() = BrokerStatus {
+            queue_capacity: self.config.handler_queue_size,
+            ..BrokerStatus::default()
+        };
+        let status = self.status.clone();
+        let codec = self.codec.clone();
+        let clock = self.clock.clone();
+        let expected_senders = self.expected_senders.clone();
+        *self.identity.lock().unwrap() = None;
+        let identity = self.identity.clone();
+        let correlations = self.correlations.clone();
+        let transform = self.transform.clone();
+        let sender_filter = self.sender_filter.clone();
+        let public_keys = self.public_keys.clone();
+        let (slate_sender, slate_receiver) = sync_channel::<IncomingSlate>(self.config.handler_queue_size);
+        thread::spawn(move || {
+            // slates are processed on their own thread so a slow handler does not stall the socket
+            let worker_handler = cloned_handler.clone();
+            let worker_status = status.clone();
+            let worker = thread::spawn(move || {
+                for (from, mut slate, meta) in slate_receiver.iter() {
+                    worker_handler.lock().unwrap().on_slate_with_meta(&from, &mut slate, &meta);
+                    let mut status = worker_status.lock().unwrap();
+                    status.pending_slates = status.pending_slates.saturating_sub(1);
+                }
+            });
+
+            let mut subscribed = false;
+            let mut attempt = 0;
+            let mut relay = 0;
+            let mut unreachable = 0;
+            let mut dropped_at = None;
+            let mut relay_error = None;
+            let result = loop {
+                if *stop_signal.lock().unwrap() {
+                    break Ok(());
+                }
+                let url = urls[relay].clone();
+                *relay_url.lock().unwrap() = Some(url.clone());
+
+                let cloned_cloned_inner = cloned_inner.clone();
+                let client_handler = cloned_handler.clone();
+                // a rotated key also has to be used for every later connection
+                let (cloned_address, secret_key, signer) = match *identity.lock().unwrap() {
+                    Some((ref address, ref secret_key, ref signer)) => (address.clone(), secret_key.clone(), signer.clone()),
+                    None => (cloned_address.clone(), secret_key.clone(), signer.clone()),
+                };
+                let cloned_identity = identity.clone();
+                let cloned_correlations = correlations.clone();
+                let cloned_transform = transform.clone();
+                let cloned_sender_filter = sender_filter.clone();
+                let cloned_public_keys = public_keys.clone();
+                let client_config = config.clone();
+                let capabilities = capabilities.clone();
+                let connections = connections.clone();
+                let slate_sender = slate_sender.clone();
+                let cloned_tls = tls.clone();
+                let cloned_challenge = challenge.clone();
+                let cloned_status = status.clone();
+                let cloned_codec = codec.clone();
+                let cloned_clock = clock.clone();
+                let cloned_expected_senders = expected_senders.clone();
+                let rejected = Arc::new(Mutex::new(false));
+                let cloned_rejected = rejected.clone();
+                let closed_by_relay = Arc::new(Mutex::new(None));
+                let cloned_closed_by_relay = closed_by_relay.clone();
+                let reestablished = subscribed;
+                let opened = Arc::new(Mutex::new(false));
+                let cloned_opened = opened.clone();
+                let finished = Arc::new(Mutex::new(false));
+                let migrating = Arc::new(Mutex::new(false));
+                if let (true, Some(cooldown)) = (relay > 0, config.relay_migrate_back_ms) {
+                    // drop the fallback after the cooldown so the next cycle tries the primary again
+                    let inner = cloned_inner.clone();
+                    let opened = opened.clone();
+                    let finished = finished.clone();
+                    let migrating = migrating.clone();
+                    let clock = clock.clone();
+                    thread::spawn(move || {
+                        clock.sleep(Duration::from_millis(cooldown));
+                        let finished = finished.lock().unwrap();
+                        if !*finished && *opened.lock().unwrap() {
+                            if let Some(ref sender) = *inner.lock().unwrap() {
+                                *migrating.lock().unwrap() = true;
+                                sender.close(CloseCode::Normal).is_ok();
+                            }
+                        }
+                    });
+                }
+                let result = connect(&url, connect_timeout, &config, clock.clone(), opened.clone(), move |sender| {
+                    if let Ok(mut guard) = cloned_cloned_inner.lock() {
+                        *guard = Some(sender.clone());
+                    };
+
+                    let is_reconnect = {
+                        let mut guard = connections.lock().unwrap();
+                        *guard += 1;
+                        *guard > 1
+                    };
+
+                    let client = GrinboxClient {
+                        sender,
+                        handler: client_handler.clone(),
+                        challenge: cloned_challenge.clone(),
+                        relay_public_key: None,
+                        address: cloned_address.clone(),
+                        secret_key,
+                        signer: signer.clone(),
+                        config: client_config.clone(),
+                        capabilities: capabilities.clone(),
+                        is_reconnect,
+                        reestablished,
+                        slates: slate_sender.clone(),
+                        tls: cloned_tls.clone(),
+                        opened: cloned_opened.clone(),
+                        gate: OpenGate::default(),
+                        keepalive_interval_ms: KEEPALIVE_INTERVAL_MS,
+                        ping_sent_at: None,
+                        keepalive_due: None,
+                        subscribe_attempts: 0,
+                        awaiting_subscribe: false,
+                        stale_subscribes: 0,
+                        rejected: cloned_rejected.clone(),
+                        relay_error: cloned_closed_by_relay.clone(),
+                        status: cloned_status.clone(),
+                        codec: cloned_codec.clone(),
+                        clock: cloned_clock.clone(),
+                        expected_senders: cloned_expected_senders.clone(),
+                        identity: cloned_identity.clone(),
+                        correlations: cloned_correlations.clone(),
+                        transform: cloned_transform.clone(),
+                        public_keys: cloned_public_keys.clone(),
+                        last_sequence: None,
+                        sender_filter: cloned_sender_filter.clone(),
+                    };
+                    client
+                });
+
+                *finished.lock().unwrap() = true;
+                status.lock().unwrap().connected_since = None;
+                let opened = *opened.lock().unwrap();
+                if !opened {
+                    unreachable += 1;
+                }
+                let exhausted = unreachable >= urls.len();
+
+                // the challenge belonged to the connection that just ended
+                *challenge.lock().unwrap() = None;
+                if *rejected.lock().unwrap() {
+                    break Err(Wallet713Error::SubscribeRejected.into());
+                }
+                // reconnecting would only get the same answer
+                if let Some(description) = closed_by_relay.lock().unwrap().take() {
+                    relay_error = Some(description);
+                    break Ok(());
+                }
+                match tls.take_error() {
+                    Some(e) if !subscribed && exhausted => break Err(e),
+                    Some(e) => warn!("could not restore connection to {}: {}", url, e),
+                    None => {},
+                }
+                if *stop_signal.lock().unwrap() {
+                    break result;
+                }
+                if *migrating.lock().unwrap() {
+                    relay = 0;
+                    unreachable = 0;
+                    subscribed = true;
+                    cloned_handler.lock().unwrap().on_reconnecting(&urls[relay]);
+                    continue;
+                }
+                if opened {
+                    cloned_handler.lock().unwrap().on_dropped();
+                    subscribed = true;
+                    attempt = 0;
+                    unreachable = 0;
+                    dropped_at = Some(clock.now());
+                } else if !exhausted {
+                    // try the next relay right away, there is no point in waiting on another one
+                    relay = (relay + 1) % urls.len();
+                    warn!("could not reach {}, trying {}", url, urls[relay]);
+                    cloned_handler.lock().unwrap().on_reconnecting(&urls[relay]);
+                    continue;
+                } else if !subscribed {
+                    // never got through to any relay, so there is nothing to restore
+                    break result;
+                } else {
+                    // all of them are down, start over with the primary once the backoff passed
+                    relay = 0;
+                    unreachable = 0;
+                }
+
+                if let (Some(dropped_at), Some(max_duration_ms)) = (dropped_at, config.max_reconnect_duration_ms) {
+                    if clock.now().duration_since(dropped_at) >= Duration::from_millis(max_duration_ms) {
+                        break Err(Wallet713Error::ReconnectWindowExceeded(max_duration_ms).into());
+                    }
+                }
+                let delay = reconnect_delay(&config, attempt);
+                attempt += 1;
+                debug!("reconnecting to {} in {} ms (attempt {})", url, delay.as_secs() * 1000 + delay.subsec_millis() as u64, attempt);
+                clock.sleep(delay);
+            };
+
+            // the factories and their clients are gone now, so the worker stops once it drained the queue
+            drop(slate_sender);
+            if worker.join().is_err() {
+                error!("the slate worker thread panicked");
+            }
+
+            if let Ok(mut guard) = cloned_inner.lock() {
+                *guard = None;
+            };
+            *relay_url.lock().unwrap() = None;
+            *subscription.lock().unwrap() = None;
+            *handler_slot.lock().unwrap() = None;
+
+            let reason = match (relay_error, result) {
+                (Some(description), _) => CloseReason::ProtocolError(description),
+                (None, Err(e)) => CloseReason::Abnormal(e),
+                (None, Ok(_)) => CloseReason::Normal,
+            };
+            // stored before the handler hears about it, so it is there once `on_close` fired
+            *last_close_reason.lock().unwrap() = Some(reason.clone());
+            handler.lock().unwrap().on_close(reason);
+        });
+        Ok(())
+    }
+
+    fn stop(&self) {
+        *self.stop_signal.lock().unwrap() = true;
+        let mut guard = self.inner.lock().unwrap();
+        if let Some(ref sender) = *guard {
+            if let Err(e) = self.unsubscribe(sender) {
+                debug!("could not unsubscribe: {}", e);
+            }
+            sender.close(CloseCode::Normal).is_ok();
+        }
+        *guard = None;
+    }
+
+    // the client picks the new identity up with the fresh challenge asked for here
+    #[cfg(test)]
+    fn rotate_key(&self, identity: Identity) -> Result<(), Error> {
+        let sender = self.inner.lock().unwrap().clone().ok_or(Wallet713Error::ClosedListener("grinbox".to_string()))?;
+        *self.subscription.lock().unwrap() = Some((identity.0.public_key.clone(), identity.2.clone()));
+        *self.identity.lock().unwrap() = Some(identity);
+        let request = serde_json::to_string(&ProtocolRequest::Challenge)?;
+        log_wire(self.config.log_wire, ">", &request);
+        sender.send(request)?;
+        Ok(())
+    }
+
+    fn stop_graceful(&self, timeout: Duration) -> bool {
+        // the relay stops sending us slates, the connection stays up for those still on the way
+        if let Some(ref sender) = *self.inner.lock().unwrap() {
+            if let Err(e) = self.unsubscribe(sender) {
+                debug!("could not unsubscribe: {}", e);
+            }
+        }
+        let start = self.clock.now();
+        let drained = loop {
+            if self.status.lock().unwrap().pending_slates == 0 {
+                break true;
+            }
+            if self.clock.now().duration_since(start) >= timeout {
+                break false;
+            }
+            self.clock.sleep(Duration::from_millis(10));
+        };
+        self.stop();
+        drained
+    }
+
+    // lets the relay drop our subscription right away instead of after the connection timed out
+    fn unsubscribe(&self, sender: &Sender) -> Result<(), Error> {
+        let challenge = match *self.challenge.lock().unwrap() {
+            Some(ref challenge) => challenge.clone(),
+            None => return Ok(()),
+        };
+        let (address, signer) = match *self.subscription.lock().unwrap() {
+            Some((ref address, ref signer)) => (address.clone(), signer.clone()),
+            None => return Ok(()),
+        };
+        let signature = signer.sign(&challenge)?;
+        let request = serde_json::to_string(&ProtocolRequest::Unsubscribe { address, signature })?;
+        log_wire(self.config.log_wire, ">", &request);
+        sender.send(request)?;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        let guard = self.inner.lock().unwrap();
+        guard.is_some()
+    }
+}
+
+// closes the subscription once the last clone goes away, so the relay does not keep it alive
+// until it times out
+impl Drop for GrinboxBroker {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.owners) == 1 {
+            self.stop();
+        }
+    }
+}
+
+// like `ws::connect` but gives up if the connection is not open within `timeout`, instead of
+// waiting on the os to time out the tcp or tls handshake
+fn connect<F, H>(url: &str, timeout: Duration, config: &BrokerConfig, clock: SharedClock, opened: Arc<Mutex<bool>>, mut factory: F) -> Result<(), Error>
+    where F: FnMut(Sender) -> H, H: Handler
+{
+    let target = Url::parse(url)?;
+    let proxy = match config.http_proxy {
+        Some(ref proxy) => Some(HttpProxy::parse(proxy)?),
+        None => None,
+    };
+    let dial = match proxy {
+        // there is no way in before the upgrade request of a plain connection
+        Some(_) if target.scheme() != "wss" => Err(Wallet713Error::ProxyConnect(format!("{} is not a wss relay", url)))?,
+        Some(ref proxy) => proxy.url_for(&target)?,
+        None => target.clone(),
+    };
+    let failure = Arc::new(Mutex::new(None));
+    let cloned_failure = failure.clone();
+    let mut factory = move |sender: Sender| ProxyHandler {
+        inner: factory(sender),
+        proxy: proxy.clone(),
+        target: target.clone(),
+        timeout,
+        failure: cloned_failure.clone(),
+    };
+
+    let builder = Builder::new().with_settings(socket_settings(config));
+    // the handlers never see compressed frames, the deflate handler passes everything through
+    // untouched when the relay declines the extension
+    let result = match config.websocket_compression {
+        true => run_socket(builder.build(move |sender| DeflateHandler::new(factory(sender)))?, dial, url, timeout, clock, opened),
+        false => run_socket(builder.build(factory)?, dial, url, timeout, clock, opened),
+    };
+    if let Some(reason) = failure.lock().unwrap().take() {
+        Err(Wallet713Error::ProxyConnect(reason))?;
+    }
+    result
+}
+
+fn run_socket<F: Factory>(mut socket: WebSocket<F>, dial: Url, url: &str, timeout: Duration, clock: SharedClock, opened: Arc<Mutex<bool>>) -> Result<(), Error> {
+    socket.connect(dial)?;
+    let broadcaster = socket.broadcaster();
+    let timed_out = Arc::new(Mutex::new(false));
+    let cloned_timed_out = timed_out.clone();
+    let finished = Arc::new(Mutex::new(false));
+    let cloned_finished = finished.clone();
+    // only watches until the socket opened or is gone, not for the whole timeout
+    thread::spawn(move || {
+        let deadline = clock.now() + timeout;
+        loop {
+            if *opened.lock().unwrap() || *cloned_finished.lock().unwrap() {
+                return;
+            }
+            let now = clock.now();
+            if now >= deadline {
+                *cloned_timed_out.lock().unwrap() = true;
+                broadcaster.shutdown().is_ok();
+                return;
+            }
+            clock.sleep((deadline - now).min(Duration::from_millis(CONNECT_WATCHDOG_STEP_MS)));
+        }
+    });
+    let result = socket.run();
+    *finished.lock().unwrap() = true;
+    result?;
+    if *timed_out.lock().unwrap() {
+        Err(Wallet713Error::ConnectTimeout(url.to_string()))?;
+    }
+    Ok(())
+}
+
+// large slates may arrive split over several frames. ws only hands complete messages to
+// `on_message`, as long as it is allowed to buffer as many fragments as it takes.
+fn socket_settings(config: &BrokerConfig) -> Settings {
+    Settings {
+        fragments_grow: true,
+        in_buffer_grow: true,
+        max_fragment_size: config.max_message_size,
+        ..Settings::default()
+    }
+}
+
+// a suspended machine stops the monotonic clock the timers run on, but not the wall clock. so
+// after a resume the wall clock looks like it jumped ahead of where the timers expect it.
+fn clock_jumped(expected: SystemTime, actual: SystemTime, max_drift_ms: u64) -> bool {
+    let drift = match actual.duration_since(expected) {
+        Ok(x) => x,
+        Err(e) => e.duration(),
+    };
+    drift > Duration::from_millis(max_drift_ms)
+}
+
+// pings more often while the relay is slow to answer, so a dying link is noticed sooner, and
+// backs off again while it is responsive
+fn adapt_keepalive_interval(config: &BrokerConfig, interval_ms: u64, rtt_ms: u64) -> u64 {
+    let interval_ms = match rtt_ms > config.keepalive_slow_rtt_ms {
+        true => interval_ms / 2,
+        false => interval_ms + interval_ms / 4,
+    };
+    interval_ms.max(config.min_keepalive_interval_ms).min(config.max_keepalive_interval_ms)
+}
+
+// exponential backoff with a random spread, so clients dropped by a relay restart do not all
+// come back at the same moment
+fn reconnect_delay(config: &BrokerConfig, attempt: u32) -> Duration {
+    let backoff = config.reconnect_backoff_ms
+        .saturating_mul(1 << attempt.min(16))
+        .min(config.max_reconnect_backoff_ms.max(config.reconnect_backoff_ms));
+    let jitter = config.reconnect_jitter.max(0.0).min(1.0);
+    let factor = match jitter > 0.0 {
+        true => 1.0 + thread_rng().gen_range(-jitter, jitter),
+        false => 1.0,
+    };
+    Duration::from_millis((backoff as f64 * factor) as u64)
+}
+
+// the from address as posted. the full one always names the relay and the port it is reached
+// on, as the receiver can not know the port this broker defaults to.
+fn from_address(config: &BrokerConfig, from: &GrinboxAddress) -> String {
+    match config.from_address_policy {
+        FromAddressPolicy::Stripped => from.stripped(),
+        FromAddressPolicy::Full => format!("grinbox://{}@{}:{}", from.public_key, from.domain, relay_port(config, from)),
+    }
+}
+
+// a port given in the address always wins over the configured default
+pub fn relay_port(config: &BrokerConfig, address: &GrinboxAddress) -> u16 {
+    address.port.or(config.default_port).unwrap_or(DEFAULT_GRINBOX_PORT)
+}
+
+#[cfg(test)]
+fn derived_address(keys: &AddressKeySource, index: u32, domain: &str, port: Option<u16>) -> Result<(GrinboxAddress, SecretKey), Error> {
+    let secret_key = keys.address_key(index)?;
+    Ok((GrinboxAddress::from_secret_key(&secret_key, domain.to_string(), port)?, secret_key))
+}
+
+fn log_wire(enabled: bool, direction: &str, json: &str) {
+    if enabled {
+        trace!("{} {}", direction, redacted(json));
+    }
+}
+
+// the message with signatures, encrypted subscriptions and slate bodies replaced by their size
+fn redacted(json: &str) -> String {
+    let mut value: Value = match serde_json::from_str(json) {
+        Ok(x) => x,
+        Err(_) => return format!("<{} bytes that are not json>", json.len()),
+    };
+    let is_slate = match value.get("type").and_then(|t| t.as_str()) {
+        Some("Slate") | Some("PostSlate") => true,
+        _ => false,
+    };
+    if let Some(fields) = value.as_object_mut() {
+        for (key, field) in fields.iter_mut() {
+            if key == "signature" || key == "message" || (is_slate && key == "str") {
+                let len = field.as_str().map(|s| s.len()).unwrap_or(0);
+                *field = Value::from(format!("<redacted {} bytes>", len));
+            }
+        }
+    }
+    value.to_string()
+}
+
+// how far the local clock is ahead of the relay clock, negative when it is behind. the timestamp
+// comes from the relay, one too large to make sense just saturates.
+fn clock_skew_ms(relay_timestamp: u64, now: SystemTime) -> i64 {
+    let now = match now.duration_since(UNIX_EPOCH) {
+        Ok(x) => x,
+        Err(_) => return 0,
+    };
+    let now_ms = (now.as_secs().min(i64::max_value() as u64 / 1000) as i64 * 1000).saturating_add(now.subsec_millis() as i64);
+    let relay_ms = relay_timestamp.checked_mul(1000).unwrap_or(u64::max_value()).min(i64::max_value() as u64) as i64;
+    now_ms.saturating_sub(relay_ms)
+}
+
+// a gap means the relay dropped slates in between, those arriving late or twice are no gap.
+// nothing can follow the last sequence number, the relay sending more is a protocol error.
+fn track_sequence(last: &mut Option<u64>, got: u64) -> Result<(), Error> {
+    let previous = last.take();
+    *last = Some(previous.map_or(got, |previous| previous.max(got)));
+    match previous.map(|previous| previous.checked_add(1)) {
+        Some(None) => Err(Wallet713Error::SequenceOverflow)?,
+        Some(Some(expected)) if got > expected => Err(Wallet713Error::SequenceGap { expected, got })?,
+        _ => Ok(()),
+    }
+}
+
+// a slate from `from` to `to` as it comes out of the encryption. a `DecryptionError` says what
+// was wrong with the ciphertext, `Wallet713Error::UnsupportedEncryptionVersion` that it is from
+// a newer client, anything else that it was not an encrypted message at all.
+fn decrypt_slate(raw: &str, from: &GrinboxAddress, to: &GrinboxAddress, require_bound: bool, secret_key: &SecretKey) -> Result<String, Error> {
+    let message: EncryptedMessage = serde_json::from_str(raw)?;
+    let public_key = from.public_key()?;
+    message.decrypt_bound(&from.public_key, &to.public_key, require_bound, &public_key, secret_key)
+}
+
+// slates themselves never contain a line break, so the memo can not be mistaken for a part of it
+fn signed_slate_message(config: &BrokerConfig, slate_str: &str, memo: Option<&str>, challenge: &str) -> String {
+    let mut signed = String::new();
+    if let Some(ref domain) = config.signature_domain {
+        signed.push_str(domain);
+        signed.push('\n');
+    }
+    signed.push_str(slate_str);
+    if let Some(memo) = memo {
+        signed.push('\n');
+        signed.push_str(memo);
+        signed.push('\n');
+    }
+    signed.push_str(challenge);
+    signed
+}
+
+fn fallback_url(config: &BrokerConfig, relay: &str) -> String {
+    let relay = relay.trim().to_lowercase();
+    match relay.contains(':') {
+        true => format!("wss://{}", relay),
+        false => format!("wss://{}:{}", relay, config.default_port.unwrap_or(DEFAULT_GRINBOX_PORT)),
+    }
+}
+
+fn accepts_sender(filter: &Option<SharedSenderFilter>, from: &GrinboxAddress) -> bool {
+    match *filter {
+        Some(ref filter) if !filter.accepts(from) => {
+            warn!("rejected slate from {}, the sender is not allowed", from.stripped());
+            false
+        },
+        _ => true,
+    }
+}
+
+// the same key on the relay that would be dialed for both
+fn same_relay_address(config: &BrokerConfig, a: &GrinboxAddress, b: &GrinboxAddress) -> bool {
+    a.public_key == b.public_key &&
+        a.domain == b.domain &&
+        relay_port(config, a) == relay_port(config, b)
+}
+
+struct GrinboxClient {
+    sender: Sender,
+    handler: SharedHandler,
+    challenge: Arc<Mutex<Option<String>>>,
+    relay_public_key: Option<String>,
+    address: GrinboxAddress,
+    secret_key: SecretKey,
+    signer: SharedSigner,
+    config: BrokerConfig,
+    capabilities: Arc<Mutex<RelayCapabilities>>,
+    is_reconnect: bool,
+    reestablished: bool,
+    slates: SyncSender<IncomingSlate>,
+    tls: TlsConnector,
+    opened: Arc<Mutex<bool>>,
+    gate: OpenGate,
+    keepalive_interval_ms: u64,
+    // on the monotonic and on the wall clock, to tell a slow pong from a suspend in between
+    ping_sent_at: Option<(Instant, SystemTime)>,
+    // when the next keepalive should fire by the wall clock
+    keepalive_due: Option<SystemTime>,
+    subscribe_attempts: u32,
+    awaiting_subscribe: bool,
+    // subscribes signed over a challenge the relay replaced before it answered them
+    stale_subscribes: u32,
+    rejected: Arc<Mutex<bool>>,
+    // set when the relay answered with a fatal error, the subscription ends with it
+    relay_error: Arc<Mutex<Option<String>>>,
+    status: Arc<Mutex<BrokerStatus>>,
+    codec: SharedCodec,
+    clock: SharedClock,
+    expected_senders: Arc<Mutex<HashMap<String, String>>>,
+    identity: Arc<Mutex<Option<Identity>>>,
+    correlations: SharedCorrelations,
+    transform: Option<SharedTransform>,
+    public_keys: SharedKeyCache,
+    last_sequence: Option<u64>,
+    sender_filter: Option<SharedSenderFilter>,
+}
+
+// holds back whatever the relay sends until `on_open` has fully set up the client, so a
+// challenge arriving right away never sees half initialized state
+#[derive(Default)]
+struct OpenGate {
+    open: bool,
+    pending: Vec<Message>,
+}
+
+impl OpenGate {
+    fn admit(&mut self, msg: Message) -> Option<Message> {
+        if self.open {
+            return Some(msg);
+        }
+        self.pending.push(msg);
+        None
+    }
+
+    fn open(&mut self) -> Vec<Message> {
+        self.open = true;
+        self.pending.drain(..).collect()
+    }
+}
+
+impl GrinboxClient {
+    // switches to a rotated key, returning what is needed to unsubscribe the previous one
+    fn rotate_identity(&mut self) -> Option<(String, SharedSigner)> {
+        let (address, secret_key, signer) = match *self.identity.lock().unwrap() {
+            Some(ref identity) if identity.0 != self.address => identity.clone(),
+            _ => return None,
+        };
+        let previous = (self.address.public_key.clone(), self.signer.clone());
+        self.address = address;
+        self.secret_key = secret_key;
+        self.signer = signer;
+        Some(previous)
+    }
+
+    fn subscribe(&mut self, challenge: &str) -> Result<(), Error> {
+        self.subscribe_attempts += 1;
+        // a new subscription starts counting from scratch
+        self.last_sequence = None;
+        self.awaiting_subscribe = true;
+        let signature = self.signer.sign(challenge)?;
+        let request = ProtocolRequest::Subscribe {
+            address: self.address.public_key.to_string(),
+            signature,
+            slate_versions: supported_slate_versions(),
+        };
+        let request = match self.config.encrypt_subscribe {
+            true => self.encrypt_request(&request)?,
+            false => request,
+        };
+        self.send(&request)?;
+
+        // after a reconnect make sure the relay still ties this connection to our address
+        if self.config.verify_channel_binding && self.is_reconnect && self.capabilities.lock().unwrap().supports(CAPABILITY_CHANNEL_BINDING) {
+            self.send(&ProtocolRequest::Binding { address: self.address.public_key.to_string() })?;
+        }
+        Ok(())
+    }
+
+    fn verify_binding(&self, address: &str, signature: &str) -> Result<(), Error> {
+        let challenge = self.challenge.lock().unwrap().clone().ok_or(Wallet713Error::ChannelBindingMismatch)?;
+        if address != self.address.public_key {
+            Err(Wallet713Error::ChannelBindingMismatch)?;
+        }
+        let signature = Signature::from_hex(signature)?;
+        verify_signature(&challenge, &signature, &self.address.public_key()?)
+            .map_err(|_| Wallet713Error::ChannelBindingMismatch)?;
+        Ok(())
+    }
+
+    // wraps the request for the relay using a one-off key so a passive observer between us and
+    // the relay can not tell which address is subscribing
+    fn encrypt_request(&self, request: &ProtocolRequest) -> Result<ProtocolRequest, Error> {
+        let relay_public_key = match self.relay_public_key {
+            Some(ref key) if self.capabilities.lock().unwrap().supports(CAPABILITY_SECURE_SUBSCRIBE) => PublicKey::from_hex(key)?,
+            _ => Err(Wallet713Error::EncryptedSubscribeUnsupported)?,
+        };
+        let ephemeral_key = generate_secret_key()?;
+        let message = EncryptedMessage::new(serde_json::to_string(request)?, &relay_public_key, &ephemeral_key)?;
+        Ok(ProtocolRequest::SecureSubscribe {
+            public_key: public_key_from_secret_key(&ephemeral_key)?.to_hex(),
+            message: serde_json::to_string(&message)?,
+        })
+    }
+
+    // the signature itself is checked by secp256k1, which does not compare it byte by byte
+    fn verify_slate_signature(&self, from: &str, str: &str, memo: Option<&str>, challenge: &str, signature: &str) -> Result<(), Error> {
+        let public_key = self.public_keys.lock().unwrap().public_key(from)?;
+        let signature = Signature::from_hex(signature)?;
+        let signed = signed_slate_message(&self.config, str, memo, challenge);
+        verify_signature(&signed, &signature, &public_key)?;
+        Ok(())
+    }
+
+    // the relay vouches for the from address, so make sure a slate we sent out comes back
+    // from the address we sent it to and not from whoever the relay claims
+    fn check_sender(&self, from: &GrinboxAddress, slate: &Slate) -> Result<(), Error> {
+        let slate_id = slate.id.to_string();
+        let mut expected_senders = self.expected_senders.lock().unwrap();
+        let expected = match expected_senders.get(&slate_id) {
+            Some(expected) => expected.clone(),
+            None => return Ok(()),
+        };
+        if expected != from.stripped() {
+            Err(Wallet713Error::SlateSenderMismatch(slate_id, expected, from.stripped()))?;
+        }
+        expected_senders.remove(&slate_id);
+        Ok(())
+    }
+
+    fn parse_slate(&self, from: &GrinboxAddress, slate_str: &str) -> Option<Slate> {
+        match self.codec.decode(slate_str) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                match rejected_slate_version(&e) {
+                    Some(version) => {
+                        warn!("rejected slate from {}: {}", from, e);
+                        self.handler.lock().unwrap().on_slate_version_unsupported(from, version);
+                    },
+                    None => debug!("could not parse slate from {}: {}", from, e),
+                }
+                None
+            },
+        }
+    }
+
+    fn dispatch_slate(&self, slate: IncomingSlate) {
+        {
+            let mut status = self.status.lock().unwrap();
+            status.last_slate_at = Some(self.clock.system_time());
+            status.pending_slates += 1;
+            status.peak_pending_slates = status.peak_pending_slates.max(status.pending_slates);
+        }
+        let slate = match self.slates.try_send(slate) {
+            Ok(_) => return,
+            Err(TrySendError::Full(slate)) => slate,
+            Err(TrySendError::Disconnected(_)) => {
+                error!("slate handler is gone, dropping slate!");
+                self.slate_dropped();
+                return;
+            },
+        };
+        warn!("slate handler can not keep up, waiting for it to catch up...");
+        self.status.lock().unwrap().backpressure_waits += 1;
+        if self.slates.send(slate).is_err() {
+            error!("slate handler is gone, dropping slate!");
+            self.slate_dropped();
+        }
+    }
+
+    fn slate_dropped(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.pending_slates = status.pending_slates.saturating_sub(1);
+        status.dropped_slates += 1;
+    }
+
+    fn check_size(&self, size: usize) -> WsResult<()> {
+        if size > self.config.max_message_size {
+            self.sender.close(CloseCode::Size)?;
+            return Err(WsError::new(WsErrorKind::Protocol, "message exceeds the maximum size!"));
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, msg: Message) -> WsResult<()> {
+        self.check_size(msg.len())?;
+        log_wire(self.config.log_wire, "<", &msg.to_string());
+        // a message we can not make sense of is skipped, returning an error would end the
+        // connection and with it everything still to come
+        let response = match serde_json::from_str::<ProtocolResponse>(&msg.to_string()) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("skipping unparseable message from relay: {}", e);
+                return Ok(());
+            },
+        };
+        match response {
+            ProtocolResponse::Challenge { str, capabilities, public_key, timestamp } => {
+                if let Some(timestamp) = timestamp {
+                    let skew_ms = clock_skew_ms(timestamp, self.clock.system_time());
+                    let offset_ms = skew_ms.checked_abs().unwrap_or(i64::max_value()) as u64;
+                    if offset_ms > self.config.max_clock_skew_ms {
+                        // a wrong local clock otherwise only shows up as rejected subscriptions
+                        warn!("the local clock is {} ms {} the clock of the relay, check your system time!",
+                            offset_ms,
+                            match skew_ms > 0 { true => "ahead of", false => "behind" }
+                        );
+                    }
+                }
+                self.relay_public_key = public_key;
+                let lost = self.capabilities.lock().unwrap().update(capabilities, self.config.capability_downgrade_policy);
+                if !lost.is_empty() {
+                    self.handler.lock().unwrap().on_capability_downgrade(lost);
+                }
+                // the subscribe still waiting for an answer was signed over the old challenge, so
+                // the relay turns it down. that answer is not held against the one signed below.
+                if self.awaiting_subscribe {
+                    debug!("the challenge changed while subscribing, signing the new one");
+                    self.stale_subscribes += 1;
+                    self.subscribe_attempts = self.subscribe_attempts.saturating_sub(1);
+                }
+                *self.challenge.lock().unwrap() = Some(str.clone());
+                let previous = self.rotate_identity();
+                self.subscribe(&str).map_err(|_| {
+                    WsError::new(WsErrorKind::Protocol, "error attempting to subscribe!")
+                })?;
+                // only let go of the old address once the new one is subscribed
+                if let Some((address, signer)) = previous {
+                    let signature = signer.sign(&str).map_err(|e| WsError::new(WsErrorKind::Protocol, format!("could not sign unsubscribe: {}", e)))?;
+                    self.send(&ProtocolRequest::Unsubscribe { address, signature }).map_err(|_| {
+                        WsError::new(WsErrorKind::Protocol, "error unsubscribing the previous key!")
+                    })?;
+                }
+            },
+            ProtocolResponse::Slate { from, str, challenge, signature, received_at, relay, compressed, sequence, memo } => {
+                if let Some(sequence) = sequence {
+                    if let Err(e) = track_sequence(&mut self.last_sequence, sequence) {
+                        error!("{}", e);
+                    }
+                }
+                // not worth checking the signature of a slate that is not taken anyway
+                if let Ok(address) = GrinboxAddress::from_str(&from) {
+                    if !accepts_sender(&self.sender_filter, &address) {
+                        return Ok(());
+                    }
+                }
+                // a memo longer than we take is not worth dropping the slate over
+                let memo = match memo {
+                    Some(ref memo) if memo.chars().count() > self.config.max_memo_length => {
+                        warn!("ignoring the memo of a slate from {}, it is longer than {} characters", from, self.config.max_memo_length);
+                        None
+                    },
+                    memo => memo,
+                };
+                if let Err(_) = self.verify_slate_signature(&from, &str, memo.as_ref().map(|memo| &memo[..]), &challenge, &signature) {
+                    warn!("received slate with invalid signature from {}!", from);
+                    if self.config.report_unverified_slates {
+                        self.handler.lock().unwrap().on_unverified_slate(&from, &str);
+                    }
+                    return Ok(());
+                }
+                let from = match GrinboxAddress::from_str(&from) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        debug!("could not parse address `{}`: {}", from, e);
+                        return Ok(());
+                    },
+                };
+
+                let slate_str = match self.config.use_encryption {
+                    true => {
+                        match decrypt_slate(&str, &from, &self.address, self.config.require_bound_slates, &self.secret_key) {
                             Ok(x) => x,
-                            Err(_) => {
-                                cli_message!("could not parse slate!");
+                            Err(e) => {
+                                match e.downcast_ref::<DecryptionError>() {
+                                    Some(&DecryptionError::WrongKey) => warn!("{} encrypted a slate for a different address, they should check the address they send to", from),
+                                    Some(&DecryptionError::MalformedCiphertext) => warn!("slate from {} was truncated or corrupted", from),
+                                    Some(&DecryptionError::AuthTagMismatch) => warn!("slate from {} failed authentication, it may have been tampered with", from),
+                                    Some(&DecryptionError::UnsupportedVersion(_)) => warn!("could not decrypt slate from {}: {}", from, e),
+                                    None => match e.downcast_ref::<Wallet713Error>() {
+                                        Some(&Wallet713Error::AddressBinding(_)) | Some(&Wallet713Error::UnboundSlate(_)) => warn!("{}", e),
+                                        Some(&Wallet713Error::UnsupportedEncryptionVersion(_)) => warn!("could not decrypt slate from {}: {}", from, e),
+                                        _ => debug!("could not decrypt slate from {}: {}", from, e),
+                                    },
+                                }
                                 return Ok(());
                             },
+                        }
+                    },
+                    false => str,
+                };
+                let slate_str = match compressed {
+                    true => match decompress_slate(&slate_str, self.config.max_message_size) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            debug!("could not decompress slate from {}: {}", from, e);
+                            return Ok(());
                         },
-                    };
+                    },
+                    false => slate_str,
+                };
+                if slate_str.len() > self.config.max_message_size {
+                    // only the slate is at fault here, not the relay
+                    warn!("dropping slate from {} which exceeds the maximum size once decompressed", from);
+                    return Ok(());
+                }
 
-                    self.handler.lock().unwrap().on_slate(&from, &mut slate);
-                } else {
-                    cli_message!("{}: received slate with invalid signature!", "ERROR".bright_red());
+                let slate = match self.parse_slate(&from, &slate_str) {
+                    Some(x) => x,
+                    None => return Ok(()),
+                };
+                if let Err(e) = self.check_sender(&from, &slate) {
+                    error!("{}", e);
+                    return Ok(());
+                }
+                let slate = match self.transform {
+                    Some(ref transform) => match transform.transform(&from, slate) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            warn!("slate from {} was rejected by the transform: {}", from, e);
+                            return Ok(());
+                        },
+                    },
+                    None => slate,
+                };
+                if self.config.validate_slates {
+                    if let Err(e) = validate_slate(&slate) {
+                        warn!("rejected slate [{}] from {}: {}", slate.id, from, e);
+                        return Ok(());
+                    }
+                }
+                let state = {
+                    let mut correlations = self.correlations.lock().unwrap();
+                    if self.config.skip_duplicate_slates && correlations.is_duplicate(&slate.id.to_string(), &from.stripped(), self.clock.now()) {
+                        debug!("skipping slate [{}] from {}, it was handled already", slate.id, from.stripped());
+                        return Ok(());
+                    }
+                    correlations.record_incoming(&slate.id.to_string(), &from.stripped(), self.clock.now())
+                };
+                if self.config.verbose {
+                    cli_message!("slate [{}] arrived from [{}] for [{}] grins",
+                        slate.id.to_string().bright_green(),
+                        from.stripped().bright_green(),
+                        amount_to_hr_string(slate.amount, false).bright_green()
+                    );
                 }
+
+                let meta = SlateMeta {
+                    received_at,
+                    relay: relay.or_else(|| Some(format!("{}:{}", self.address.domain, relay_port(&self.config, &self.address)))),
+                    recipient: Some(self.address.to_string()),
+                    response: state == SlateState::Answered,
+                    memo,
+                };
+                self.dispatch_slate((from, slate, meta));
+            },
+            ProtocolResponse::Binding { address, signature } => {
+                if let Err(e) = self.verify_binding(&address, &signature) {
+                    error!("{}", e);
+                    self.sender.close(CloseCode::Policy)?;
+                    return Err(WsError::new(WsErrorKind::Protocol, "channel binding mismatch!"));
+                }
+                self.handler.lock().unwrap().on_channel_rebound();
+            },
+            // the relay answers in order, so the stale subscribes are answered first
+            ProtocolResponse::Ok { .. } if self.awaiting_subscribe && self.stale_subscribes > 0 => {
+                self.stale_subscribes -= 1;
+            },
+            ProtocolResponse::Error { ref kind, ref description } if kind.is_fatal() => {
+                error!("{}", response);
+                *self.relay_error.lock().unwrap() = Some(description.clone());
+                self.sender.close(CloseCode::Policy)?;
+            },
+            ProtocolResponse::Error { .. } if self.awaiting_subscribe && self.stale_subscribes > 0 => {
+                debug!("subscribe over a stale challenge failed: {}", response);
+                self.stale_subscribes -= 1;
+            },
+            ProtocolResponse::Ok { .. } => {
+                self.awaiting_subscribe = false;
             },
             ProtocolResponse::Error { kind: _, description: _ } => {
-                cli_message!("{}", response);
+                error!("{}", response);
+                if self.awaiting_subscribe {
+                    // the challenge might have gone stale, so try again on a fresh one
+                    self.awaiting_subscribe = false;
+                    if self.subscribe_attempts <= self.config.subscribe_retries {
+                        self.send(&ProtocolRequest::Challenge).map_err(|_| {
+                            WsError::new(WsErrorKind::Protocol, "error requesting a new challenge!")
+                        })?;
+                    } else {
+                        *self.rejected.lock().unwrap() = true;
+                        self.sender.close(CloseCode::Policy)?;
+                    }
+                }
             },
             _ => {}
         }
         Ok(())
     }
+
+    fn send(&self, request: &ProtocolRequest) -> Result<(), Error> {
+        let request = serde_json::to_string(&request)?;
+        log_wire(self.config.log_wire, ">", &request);
+        self.sender.send(request)?;
+        Ok(())
+    }
+
+    fn schedule_keepalive(&mut self) -> WsResult<()> {
+        self.keepalive_due = Some(self.clock.system_time() + Duration::from_millis(self.keepalive_interval_ms));
+        self.sender.timeout(self.keepalive_interval_ms, KEEPALIVE_TOKEN)
+    }
+}
+
+impl Handler for GrinboxClient {
+    fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+        *self.opened.lock().unwrap() = true;
+        {
+            let mut status = self.status.lock().unwrap();
+            status.connected_since = Some(self.clock.system_time());
+            if self.reestablished {
+                status.reconnects += 1;
+            }
+        }
+        match self.reestablished {
+            true => self.handler.lock().unwrap().on_reestablished(),
+            false => self.handler.lock().unwrap().on_open(),
+        }
+        self.schedule_keepalive()?;
+        for msg in self.gate.open() {
+            self.handle_message(msg)?;
+        }
+        Ok(())
+    }
+
+    fn upgrade_ssl_client(&mut self, sock: TcpStream, url: &Url) -> WsResult<SslStream<TcpStream>> {
+        self.tls.connect(sock, url)
+    }
+
+    fn on_timeout(&mut self, event: Token) -> WsResult<()> {
+        match event {
+            KEEPALIVE_TOKEN => {
+                if let Some(due) = self.keepalive_due {
+                    if clock_jumped(due, self.clock.system_time(), self.config.max_keepalive_drift_ms) {
+                        // whatever was measured before the suspend says nothing about the relay now
+                        debug!("the wall clock jumped, the machine was probably suspended. starting keepalive over");
+                        self.ping_sent_at = None;
+                        self.keepalive_interval_ms = KEEPALIVE_INTERVAL_MS;
+                    }
+                }
+                self.sender.ping(vec![])?;
+                self.ping_sent_at = Some((self.clock.now(), self.clock.system_time()));
+                self.schedule_keepalive()
+            }
+            _ => Err(WsError::new(WsErrorKind::Internal, "Invalid timeout token encountered!")),
+        }
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> WsResult<Option<Frame>> {
+        if frame.opcode() == OpCode::Pong && self.config.adaptive_keepalive {
+            if let Some((sent_at, sent_at_wall)) = self.ping_sent_at.take() {
+                let rtt = self.clock.now() - sent_at;
+                if clock_jumped(sent_at_wall + rtt, self.clock.system_time(), self.config.max_keepalive_drift_ms) {
+                    debug!("ignoring the pong of a ping sent before the wall clock jumped");
+                    return Ok(Some(frame));
+                }
+                let rtt_ms = rtt.as_secs() * 1000 + rtt.subsec_millis() as u64;
+                self.keepalive_interval_ms = adapt_keepalive_interval(&self.config, self.keepalive_interval_ms, rtt_ms);
+                debug!("relay rtt {} ms, keepalive every {} ms", rtt_ms, self.keepalive_interval_ms);
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    fn on_message(&mut self, msg: Message) -> WsResult<()> {
+        match self.gate.admit(msg) {
+            Some(msg) => self.handle_message(msg),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, sync_channel, SyncSender};
+    use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
+    use ws::{Builder, Settings, Sender, Handler, Handshake, Message, CloseCode, Result as WsResult};
+    use ws::deflate::DeflateHandler;
+    use common::Wallet713Error;
+    use common::crypto::{SecretKey, EncryptedMessage, DecryptionError, generate_secret_key, public_key_from_secret_key};
+    use contacts::{Address, GrinboxAddress};
+    use super::super::clock::{Clock, MockClock};
+    use super::{GrinboxBroker, GrinboxPublisher, GrinboxSubscriber, RelayCapabilities, OpenGate, GrinboxClient, ReceiveOneHandler, ResponseHandler, ObserverHandler, TlsConnector, KeySigner, JsonCodec, SystemClock, IncomingSlate, KEEPALIVE_INTERVAL_MS, reconnect_delay, adapt_keepalive_interval, clock_jumped, fallback_url, connect, from_address, same_relay_address, signed_slate_message, clock_skew_ms, track_sequence, redacted};
+    use super::super::config::{BrokerConfig, CapabilityDowngradePolicy, FromAddressPolicy, TlsVersion};
+    use super::super::protocol::{ProtocolRequest, ProtocolResponse, ProtocolError};
+    use super::super::types::{Publisher, Subscriber, SubscriptionHandler, SlateMeta, DeliveryGuarantee};
+    use super::super::correlation::CorrelationStore;
+    use super::super::key_cache::PublicKeyCache;
+    use grin_core::libtx::slate::Slate;
+
+    #[test]
+    fn detects_capability_downgrade() {
+        let mut capabilities = RelayCapabilities::default();
+        let lost = capabilities.update(vec!["a".to_string(), "b".to_string()], CapabilityDowngradePolicy::Disable);
+        assert!(lost.is_empty());
+        let lost = capabilities.update(vec!["a".to_string()], CapabilityDowngradePolicy::Disable);
+        assert_eq!(lost, vec!["b".to_string()]);
+        assert!(capabilities.supports("a"));
+        assert!(!capabilities.supports("b"));
+    }
+
+    #[test]
+    fn keeps_downgraded_capabilities_enabled() {
+        let mut capabilities = RelayCapabilities::default();
+        capabilities.update(vec!["a".to_string(), "b".to_string()], CapabilityDowngradePolicy::KeepEnabled);
+        let lost = capabilities.update(vec!["a".to_string()], CapabilityDowngradePolicy::KeepEnabled);
+        assert_eq!(lost, vec!["b".to_string()]);
+        assert!(capabilities.supports("b"));
+    }
+
+    #[test]
+    fn spreads_reconnect_delays() {
+        let mut config = BrokerConfig::default();
+        config.reconnect_backoff_ms = 1_000;
+        config.max_reconnect_backoff_ms = 8_000;
+        config.reconnect_jitter = 0.2;
+        for _ in 0..100 {
+            let delay = reconnect_delay(&config, 0);
+            assert!(delay >= Duration::from_millis(800) && delay <= Duration::from_millis(1_200));
+            let delay = reconnect_delay(&config, 10);
+            assert!(delay >= Duration::from_millis(6_400) && delay <= Duration::from_millis(9_600));
+        }
+        config.reconnect_jitter = 0.0;
+        assert_eq!(reconnect_delay(&config, 1), Duration::from_millis(2_000));
+    }
+
+    #[test]
+    fn holds_messages_until_open() {
+        let mut gate = OpenGate::default();
+        assert!(gate.admit(Message::text("challenge")).is_none());
+        assert!(gate.admit(Message::text("slate")).is_none());
+        let pending: Vec<String> = gate.open().into_iter().map(|msg| msg.to_string()).collect();
+        assert_eq!(pending, vec!["challenge".to_string(), "slate".to_string()]);
+        assert_eq!(gate.admit(Message::text("next")).unwrap().to_string(), "next");
+        assert!(gate.open().is_empty());
+    }
+
+    #[test]
+    fn adapts_keepalive_to_relay_latency() {
+        let config = BrokerConfig::default();
+        assert_eq!(adapt_keepalive_interval(&config, 30_000, 2_000), 15_000);
+        assert_eq!(adapt_keepalive_interval(&config, 30_000, 100), 37_500);
+        assert_eq!(adapt_keepalive_interval(&config, 6_000, 2_000), config.min_keepalive_interval_ms);
+        assert_eq!(adapt_keepalive_interval(&config, 110_000, 100), config.max_keepalive_interval_ms);
+    }
+
+    #[test]
+    fn detects_suspended_keepalives() {
+        let clock = MockClock::new();
+        let due = clock.system_time() + Duration::from_millis(KEEPALIVE_INTERVAL_MS);
+        clock.advance(Duration::from_millis(KEEPALIVE_INTERVAL_MS + 500));
+        assert!(!clock_jumped(due, clock.system_time(), 60_000));
+        // the lid was closed for an hour
+        clock.advance(Duration::from_secs(3600));
+        assert!(clock_jumped(due, clock.system_time(), 60_000));
+        // and a clock set back by a lot is just as far off
+        assert!(clock_jumped(clock.system_time(), due, 60_000));
+    }
+
+    #[test]
+    fn builds_fallback_relay_urls() {
+        let mut config = BrokerConfig::default();
+        assert_eq!(fallback_url(&config, "Relay.example.com"), "wss://relay.example.com:443");
+        assert_eq!(fallback_url(&config, "relay.example.com:13420"), "wss://relay.example.com:13420");
+        config.default_port = Some(13420);
+        assert_eq!(fallback_url(&config, " relay.example.com "), "wss://relay.example.com:13420");
+    }
+
+    #[test]
+    fn separates_signature_domains() {
+        let mut config = BrokerConfig::default();
+        assert_eq!(signed_slate_message(&config, "slate", None, "challenge"), "slatechallenge");
+        config.signature_domain = Some("grinbox-v1".to_string());
+        assert_eq!(signed_slate_message(&config, "slate", None, "challenge"), "grinbox-v1\nslatechallenge");
+        assert_eq!(signed_slate_message(&config, "slate", Some("invoice 42"), "challenge"), "grinbox-v1\nslate\ninvoice 42\nchallenge");
+    }
+
+    #[test]
+    fn redacts_wire_messages() {
+        let logged = redacted("{\"type\":\"PostSlate\",\"from\":\"a\",\"to\":\"b\",\"str\":\"secret\",\"signature\":\"3045\"}");
+        assert!(logged.contains("\"from\":\"a\""));
+        assert!(logged.contains("<redacted 6 bytes>"));
+        assert!(!logged.contains("secret") && !logged.contains("3045"));
+        let logged = redacted("{\"type\":\"Challenge\",\"str\":\"challenge\"}");
+        assert!(logged.contains("\"str\":\"challenge\""));
+    }
+
+    #[test]
+    fn measures_clock_skew() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(clock_skew_ms(1_000_000, now), 0);
+        assert_eq!(clock_skew_ms(999_940, now), 60_000);
+        assert_eq!(clock_skew_ms(1_000_005, now), -5_000);
+        // whatever the relay sends, it never overflows
+        assert_eq!(clock_skew_ms(u64::max_value(), now), 1_000_000_000 - i64::max_value());
+        assert!(clock_skew_ms(u64::max_value(), now).checked_abs().is_some());
+    }
+
+    #[test]
+    fn detects_sequence_gaps() {
+        let mut last = None;
+        assert!(track_sequence(&mut last, 7).is_ok());
+        assert!(track_sequence(&mut last, 8).is_ok());
+        assert!(track_sequence(&mut last, 8).is_ok());
+        assert!(track_sequence(&mut last, 11).is_err());
+        assert!(track_sequence(&mut last, 9).is_ok());
+        assert!(track_sequence(&mut last, 12).is_ok());
+        assert_eq!(last, Some(12));
+
+        let mut last = None;
+        assert!(track_sequence(&mut last, u64::max_value()).is_ok());
+        match track_sequence(&mut last, u64::max_value()) {
+            Err(ref e) => match e.downcast_ref::<Wallet713Error>() {
+                Some(&Wallet713Error::SequenceOverflow) => {},
+                _ => panic!("unexpected error: {}", e),
+            },
+            Ok(_) => panic!("the overflow was not reported"),
+        }
+    }
+
+    #[test]
+    fn throttles_on_the_injected_clock() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "127.0.0.1".to_string(), None);
+        let mut config = BrokerConfig::default();
+        config.max_posts_per_second = Some(1.0);
+        let mut publisher = GrinboxPublisher::with_config(&address, &secret_key, config).unwrap();
+        let clock = Arc::new(MockClock::new());
+        publisher.set_clock(clock.clone());
+        let start = clock.now();
+        for _ in 0..3 {
+            publisher.throttle().unwrap();
+        }
+        let waited = clock.now() - start;
+        assert!(waited >= Duration::from_secs(1) && waited < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn posts_normalized_full_from_addresses() {
+        let secret_key = generate_secret_key().unwrap();
+        let from = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "GrinBox.io".to_string(), Some(443));
+        let mut config = BrokerConfig::default();
+        assert_eq!(from_address(&config, &from), from.stripped());
+
+        config.from_address_policy = FromAddressPolicy::Full;
+        assert_eq!(from_address(&config, &from), format!("grinbox://{}@grinbox.io:443", from.public_key));
+        assert_eq!(GrinboxAddress::from_str(&from_address(&config, &from)).unwrap(), from);
+
+        // the port named in the address wins over the default port of the broker
+        config.default_port = Some(5555);
+        assert_eq!(from_address(&config, &from), format!("grinbox://{}@grinbox.io:443", from.public_key));
+        let without_port = GrinboxAddress { port: None, ..from.clone() };
+        assert_eq!(from_address(&config, &without_port), format!("grinbox://{}@grinbox.io:5555", from.public_key));
+    }
+
+    #[test]
+    fn posts_to_the_explicit_port_over_the_default_port() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "127.0.0.1".to_string(), None);
+        let mut config = BrokerConfig::default();
+        config.default_port = Some(5555);
+        config.remember_failed_post = true;
+        config.connect_timeout_ms = 1_000;
+        let publisher = GrinboxPublisher::with_config(&address, &secret_key, config.clone()).unwrap();
+        let to = GrinboxAddress::from_str(&format!("{}@127.0.0.1:443", address.public_key)).unwrap();
+        // nothing listens there, what matters is where it was sent
+        assert!(publisher.post_slate(&Slate::blank(2), &to as &Address).is_err());
+
+        let failed_post = publisher.failed_post.lock().unwrap().clone().unwrap();
+        assert_eq!(failed_post.1.port, Some(443));
+        assert_eq!(GrinboxBroker::new(config).unwrap().url(&failed_post.1), "wss://127.0.0.1:443");
+    }
+
+    #[test]
+    fn compares_relay_addresses_by_the_dialed_port() {
+        let secret_key = generate_secret_key().unwrap();
+        let explicit = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "grinbox.io".to_string(), Some(443));
+        let implicit = GrinboxAddress { port: None, ..explicit.clone() };
+        let mut config = BrokerConfig::default();
+        assert!(same_relay_address(&config, &explicit, &implicit));
+        config.default_port = Some(5555);
+        assert!(!same_relay_address(&config, &explicit, &implicit));
+    }
+
+    #[test]
+    fn times_out_connecting_on_the_clock() {
+        // takes the tcp connection but never answers the upgrade request
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let result = connect(&url, Duration::from_secs(3600), &BrokerConfig::default(), clock.clone(), Arc::new(Mutex::new(false)), |_sender: Sender| {
+            |_msg: Message| -> WsResult<()> { Ok(()) }
+        });
+        match result {
+            Err(ref e) => match e.downcast_ref::<Wallet713Error>() {
+                Some(&Wallet713Error::ConnectTimeout(_)) => {},
+                _ => panic!("unexpected error: {}", e),
+            },
+            Ok(_) => panic!("the connection did not time out"),
+        }
+        assert!(clock.now() - start >= Duration::from_secs(3600));
+    }
+
+    struct FirstMessage {
+        sender: Sender,
+        received: Arc<Mutex<Option<String>>>,
+    }
+
+    impl Handler for FirstMessage {
+        fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+            self.sender.send("ready")
+        }
+
+        fn on_message(&mut self, msg: Message) -> WsResult<()> {
+            *self.received.lock().unwrap() = Some(msg.to_string());
+            self.sender.close(CloseCode::Normal)
+        }
+    }
+
+    #[test]
+    fn reassembles_fragmented_messages() {
+        let slate = format!("{{\"type\":\"Slate\",\"str\":\"{}\"}}", "a".repeat(4096));
+        let served = slate.clone();
+        let (bound, is_bound) = channel();
+        thread::spawn(move || {
+            // the relay side splits everything into 256 byte frames
+            let settings = Settings { fragment_size: 256, ..Settings::default() };
+            let server = Builder::new().with_settings(settings).build(move |out: Sender| {
+                let served = served.clone();
+                move |_msg| out.send(served.clone())
+            }).unwrap().bind("127.0.0.1:0").unwrap();
+            bound.send(server.local_addr().unwrap().port()).unwrap();
+            server.run().is_ok();
+        });
+        let url = format!("ws://127.0.0.1:{}", is_bound.recv().unwrap());
+
+        let received = Arc::new(Mutex::new(None));
+        let cloned_received = received.clone();
+        let config = BrokerConfig::default();
+        connect(&url, Duration::from_secs(5), &config, Arc::new(SystemClock), Arc::new(Mutex::new(false)), move |sender| FirstMessage {
+            sender,
+            received: cloned_received.clone(),
+        }).unwrap();
+        assert_eq!(received.lock().unwrap().take(), Some(slate));
+    }
+
+    #[test]
+    fn negotiates_websocket_compression() {
+        let slate = format!("{{\"type\":\"Slate\",\"str\":\"{}\"}}", "a".repeat(4096));
+        let served = slate.clone();
+        let (bound, is_bound) = channel();
+        thread::spawn(move || {
+            let server = Builder::new().build(move |out: Sender| {
+                let served = served.clone();
+                DeflateHandler::new(move |_msg| out.send(served.clone()))
+            }).unwrap().bind("127.0.0.1:0").unwrap();
+            bound.send(server.local_addr().unwrap().port()).unwrap();
+            server.run().is_ok();
+        });
+        let url = format!("ws://127.0.0.1:{}", is_bound.recv().unwrap());
+
+        // with and without the extension on our side the message arrives the same
+        for compression in vec![true, false] {
+            let received = Arc::new(Mutex::new(None));
+            let cloned_received = received.clone();
+            let config = BrokerConfig { websocket_compression: compression, ..BrokerConfig::default() };
+            connect(&url, Duration::from_secs(5), &config, Arc::new(SystemClock), Arc::new(Mutex::new(false)), move |sender| FirstMessage {
+                sender,
+                received: cloned_received.clone(),
+            }).unwrap();
+            assert_eq!(received.lock().unwrap().take(), Some(slate.clone()));
+        }
+    }
+
+    struct BadThenChallenge {
+        sender: Sender,
+        subscribed: ::std::sync::mpsc::Sender<()>,
+    }
+
+    impl Handler for BadThenChallenge {
+        fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+            self.sender.send("{ this is not json")?;
+            self.sender.send("{\"type\":\"Challenge\",\"str\":\"challenge\"}")
+        }
+
+        fn on_message(&mut self, msg: Message) -> WsResult<()> {
+            if msg.to_string().contains("Subscribe") {
+                self.subscribed.send(()).is_ok();
+                self.sender.close(CloseCode::Normal)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn test_client(sender: Sender, secret_key: &SecretKey, config: &BrokerConfig, handler: Box<SubscriptionHandler + Send>, slates: SyncSender<IncomingSlate>) -> GrinboxClient {
+        GrinboxClient {
+            sender,
+            handler: Arc::new(Mutex::new(handler)),
+            challenge: Arc::new(Mutex::new(None)),
+            relay_public_key: None,
+            address: GrinboxAddress::from_secret_key(secret_key, "127.0.0.1".to_string(), None).unwrap(),
+            secret_key: secret_key.clone(),
+            signer: Arc::new(KeySigner::new(secret_key)),
+            config: config.clone(),
+            capabilities: Arc::new(Mutex::new(RelayCapabilities::default())),
+            is_reconnect: false,
+            reestablished: false,
+            slates,
+            tls: TlsConnector::new(None, None, TlsVersion::Tls12),
+            opened: Arc::new(Mutex::new(false)),
+            gate: OpenGate::default(),
+            keepalive_interval_ms: KEEPALIVE_INTERVAL_MS,
+            ping_sent_at: None,
+            keepalive_due: None,
+            subscribe_attempts: 0,
+            awaiting_subscribe: false,
+            stale_subscribes: 0,
+            rejected: Arc::new(Mutex::new(false)),
+            relay_error: Arc::new(Mutex::new(None)),
+            status: Arc::new(Mutex::new(Default::default())),
+            codec: Arc::new(JsonCodec::new(None)),
+            clock: Arc::new(SystemClock),
+            expected_senders: Arc::new(Mutex::new(HashMap::new())),
+            identity: Arc::new(Mutex::new(None)),
+            correlations: Arc::new(Mutex::new(CorrelationStore::new(Duration::from_secs(60)))),
+            transform: None,
+            public_keys: Arc::new(Mutex::new(PublicKeyCache::new(16))),
+            last_sequence: None,
+            sender_filter: None,
+        }
+    }
+
+    #[test]
+    fn survives_a_bad_message() {
+        let (subscribed, is_subscribed) = channel();
+        let (bound, is_bound) = channel();
+        thread::spawn(move || {
+            let server = Builder::new().build(move |sender: Sender| BadThenChallenge {
+                sender,
+                subscribed: subscribed.clone(),
+            }).unwrap().bind("127.0.0.1:0").unwrap();
+            bound.send(server.local_addr().unwrap().port()).unwrap();
+            server.run().is_ok();
+        });
+        let url = format!("ws://127.0.0.1:{}", is_bound.recv().unwrap());
+
+        let secret_key = generate_secret_key().unwrap();
+        let (received, _) = channel();
+        let (slates, _queued) = sync_channel(1);
+        let config = BrokerConfig::default();
+        let client_config = config.clone();
+        connect(&url, Duration::from_secs(5), &config, Arc::new(SystemClock), Arc::new(Mutex::new(false)), move |sender| {
+            test_client(sender, &secret_key, &client_config, Box::new(ReceiveOneHandler { sender: received.clone() }), slates.clone())
+        }).unwrap();
+        assert!(is_subscribed.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    // replaces its challenge right away and turns down the subscribe signed over the first one
+    struct ChangingChallenge {
+        sender: Sender,
+        subscribes: u32,
+        events: ::std::sync::mpsc::Sender<&'static str>,
+    }
+
+    impl Handler for ChangingChallenge {
+        fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+            self.sender.send("{\"type\":\"Challenge\",\"str\":\"first\"}")?;
+            self.sender.send("{\"type\":\"Challenge\",\"str\":\"second\"}")
+        }
+
+        fn on_message(&mut self, msg: Message) -> WsResult<()> {
+            let response = match ::serde_json::from_str::<ProtocolRequest>(&msg.to_string()) {
+                Ok(ProtocolRequest::Subscribe { .. }) => {
+                    self.subscribes += 1;
+                    match self.subscribes {
+                        1 => ProtocolResponse::Error { kind: ProtocolError::InvalidChallenge, description: "stale challenge".to_string() },
+                        _ => ProtocolResponse::Ok { id: None },
+                    }
+                },
+                Ok(ProtocolRequest::Challenge) => {
+                    self.events.send("challenge requested").is_ok();
+                    return Ok(());
+                },
+                _ => return Ok(()),
+            };
+            self.sender.send(::serde_json::to_string(&response).unwrap())?;
+            if self.subscribes == 2 {
+                self.events.send("subscribed").is_ok();
+            }
+            Ok(())
+        }
+
+        fn on_close(&mut self, _code: CloseCode, _reason: &str) {
+            self.events.send("closed").is_ok();
+        }
+    }
+
+    #[test]
+    fn resubscribes_on_a_fresh_challenge() {
+        let (events, received_events) = channel();
+        let (bound, is_bound) = channel();
+        thread::spawn(move || {
+            let server = Builder::new().build(move |sender: Sender| ChangingChallenge {
+                sender,
+                subscribes: 0,
+                events: events.clone(),
+            }).unwrap().bind("127.0.0.1:0").unwrap();
+            bound.send(server.local_addr().unwrap().port()).unwrap();
+            server.run().is_ok();
+        });
+        let url = format!("ws://127.0.0.1:{}", is_bound.recv().unwrap());
+
+        let secret_key = generate_secret_key().unwrap();
+        let (received, _) = channel();
+        let (slates, _queued) = sync_channel(1);
+        // without retries any failure counted against us would end the connection
+        let config = BrokerConfig {
+            subscribe_retries: 0,
+            ..BrokerConfig::default()
+        };
+        let client_config = config.clone();
+        thread::spawn(move || {
+            connect(&url, Duration::from_secs(5), &config, Arc::new(SystemClock), Arc::new(Mutex::new(false)), move |sender| {
+                test_client(sender, &secret_key, &client_config, Box::new(ReceiveOneHandler { sender: received.clone() }), slates.clone())
+            }).is_ok();
+        });
+        assert_eq!(received_events.recv_timeout(Duration::from_secs(5)).unwrap(), "subscribed");
+        assert!(received_events.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn tries_to_decrypt_slates() {
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let sender = GrinboxAddress::from_secret_key(&sender_key, "127.0.0.1".to_string(), None).unwrap();
+        let receiver = GrinboxAddress::from_secret_key(&receiver_key, "127.0.0.1".to_string(), None).unwrap();
+        let message = EncryptedMessage::new_bound("slate".to_string(), &sender.public_key, &receiver.public_key, &receiver.public_key().unwrap(), &sender_key).unwrap();
+        let raw = ::serde_json::to_string(&message).unwrap();
+        let subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, BrokerConfig::default()).unwrap();
+        assert_eq!(subscriber.try_decrypt(&raw, &sender).unwrap(), "slate");
+
+        let other = GrinboxAddress::from_secret_key(&generate_secret_key().unwrap(), "127.0.0.1".to_string(), None).unwrap();
+        let error = subscriber.try_decrypt(&raw, &other).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::AddressBinding(_)) => {},
+            _ => panic!("unexpected error: {}", error),
+        }
+        let unbound = EncryptedMessage::new("slate".to_string(), &receiver.public_key().unwrap(), &sender_key).unwrap();
+        let error = subscriber.try_decrypt(&::serde_json::to_string(&unbound).unwrap(), &other).unwrap_err();
+        assert!(error.downcast_ref::<DecryptionError>().is_some());
+        let error = subscriber.try_decrypt("not encrypted", &sender).unwrap_err();
+        assert!(error.downcast_ref::<DecryptionError>().is_none());
+    }
+
+    #[test]
+    fn binds_slates_when_the_sender_wants_to() {
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let sender = GrinboxAddress::from_secret_key(&sender_key, "127.0.0.1".to_string(), None).unwrap();
+        let receiver = GrinboxAddress::from_secret_key(&receiver_key, "127.0.0.1".to_string(), None).unwrap();
+        let encrypted_version = |config: BrokerConfig, relay_binds: bool| -> u64 {
+            let broker = GrinboxBroker::new(config).unwrap();
+            let request = broker.post_slate_request(&Slate::blank(2), &receiver, &sender, &sender_key, &broker.signer(&sender_key), &broker.codec, "challenge", false, relay_binds, None, DeliveryGuarantee::FireAndForget).unwrap();
+            let str = match request {
+                ProtocolRequest::PostSlate { str, .. } => str,
+                _ => panic!("not a post"),
+            };
+            ::serde_json::from_str::<::serde_json::Value>(&str).unwrap()["version"].as_u64().unwrap()
+        };
+        let mut config = BrokerConfig::default();
+        assert_eq!(encrypted_version(config.clone(), false), 1);
+        assert_eq!(encrypted_version(config.clone(), true), 2);
+        config.bind_slate_addresses = true;
+        assert_eq!(encrypted_version(config, false), 2);
+    }
+
+    #[test]
+    fn can_require_bound_slates() {
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let sender = GrinboxAddress::from_secret_key(&sender_key, "127.0.0.1".to_string(), None).unwrap();
+        let receiver = GrinboxAddress::from_secret_key(&receiver_key, "127.0.0.1".to_string(), None).unwrap();
+        let unbound = ::serde_json::to_string(&EncryptedMessage::new("slate".to_string(), &receiver.public_key().unwrap(), &sender_key).unwrap()).unwrap();
+        let bound = ::serde_json::to_string(&EncryptedMessage::new_bound("slate".to_string(), &sender.public_key, &receiver.public_key, &receiver.public_key().unwrap(), &sender_key).unwrap()).unwrap();
+
+        let subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, BrokerConfig::default()).unwrap();
+        assert_eq!(subscriber.try_decrypt(&unbound, &sender).unwrap(), "slate");
+
+        let mut config = BrokerConfig::default();
+        config.require_bound_slates = true;
+        let subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, config).unwrap();
+        assert_eq!(subscriber.try_decrypt(&bound, &sender).unwrap(), "slate");
+        let error = subscriber.try_decrypt(&unbound, &sender).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::UnboundSlate(_)) => {},
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn refuses_to_subscribe_without_a_public_key() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress {
+            public_key: String::new(),
+            domain: "127.0.0.1".to_string(),
+            port: None,
+        };
+        let mut subscriber = GrinboxSubscriber::with_config(&address, &secret_key, BrokerConfig::default()).unwrap();
+        let error = subscriber.receive_one(Duration::from_secs(5)).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::MissingPublicKey(_)) => {},
+            _ => panic!("unexpected error: {}", error),
+        }
+        assert!(!subscriber.is_running());
+    }
+
+    #[test]
+    fn waits_for_the_matching_response() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "127.0.0.1".to_string(), None);
+        let other_key = generate_secret_key().unwrap();
+        let other = GrinboxAddress::new(public_key_from_secret_key(&other_key).unwrap(), "127.0.0.1".to_string(), None);
+        let mut slate = Slate::blank(2);
+        let (sender, receiver) = channel();
+        let handler = ResponseHandler {
+            slate_id: slate.id.to_string(),
+            from: address.stripped(),
+            sender,
+        };
+        handler.on_slate(&address, &mut Slate::blank(2));
+        handler.on_slate(&other, &mut slate);
+        assert!(receiver.try_recv().is_err());
+        handler.on_slate(&address, &mut slate);
+        assert_eq!(receiver.try_recv().unwrap().unwrap().unwrap().id, slate.id);
+    }
+
+    #[test]
+    fn observes_slates_untouched() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "127.0.0.1".to_string(), None);
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let cloned_observed = observed.clone();
+        let handler = ObserverHandler {
+            observer: Box::new(move |from: &GrinboxAddress, slate: &Slate, _meta: &SlateMeta| cloned_observed.lock().unwrap().push((from.stripped(), slate.clone()))),
+        };
+        let mut slate = Slate::blank(2);
+        let sent = slate.clone();
+        handler.on_slate(&address, &mut slate);
+        let observed = observed.lock().unwrap();
+        assert_eq!(observed.len(), 1);
+        assert_eq!(observed[0].0, address.stripped());
+        assert_eq!(observed[0].1.id, sent.id);
+    }
+
+    #[test]
+    fn shares_state_with_the_local_subscriber() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::from_secret_key(&secret_key, "127.0.0.1".to_string(), None).unwrap();
+        let mut subscriber = GrinboxSubscriber::with_config(&address, &secret_key, BrokerConfig::default()).unwrap();
+        let clock: Arc<Clock> = Arc::new(MockClock::new());
+        subscriber.set_clock(clock.clone());
+        assert!(Arc::ptr_eq(&subscriber.clock(), &clock));
+
+        let mut publisher = GrinboxPublisher::with_config(&address, &secret_key, BrokerConfig::default()).unwrap();
+        assert!(!Arc::ptr_eq(&publisher.correlations(), &subscriber.correlations()));
+        publisher.set_local_subscriber(&subscriber);
+        assert!(Arc::ptr_eq(&publisher.correlations(), &subscriber.correlations()));
+    }
 }
@@ -0,0 +1,65 @@
+use std::collections::{HashMap, VecDeque};
+
+use common::Error;
+use common::crypto::PublicKey;
+use contacts::{Address, GrinboxAddress};
+
+// public keys of the senders seen recently, by the exact address string they sent from. a
+// sender using another format of its address simply gets a new entry, so nothing is ever
+// served for a string it was not derived from. the oldest entries go first once full.
+pub struct PublicKeyCache {
+    capacity: usize,
+    keys: HashMap<String, PublicKey>,
+    order: VecDeque<String>,
+}
+
+impl PublicKeyCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            keys: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn public_key(&mut self, address: &str) -> Result<PublicKey, Error> {
+        if let Some(public_key) = self.keys.get(address) {
+            return Ok(public_key.clone());
+        }
+        let public_key = GrinboxAddress::from_str(address)?.public_key()?;
+        if self.capacity == 0 {
+            return Ok(public_key);
+        }
+        if self.keys.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        self.keys.insert(address.to_string(), public_key.clone());
+        self.order.push_back(address.to_string());
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use common::crypto::{generate_secret_key, public_key_from_secret_key};
+    use contacts::GrinboxAddress;
+    use super::PublicKeyCache;
+
+    #[test]
+    fn caches_a_bounded_number_of_keys() {
+        let mut cache = PublicKeyCache::new(2);
+        let addresses: Vec<String> = (0..3).map(|_| {
+            let public_key = public_key_from_secret_key(&generate_secret_key().unwrap()).unwrap();
+            GrinboxAddress::new(public_key, "127.0.0.1".to_string(), None).to_string()
+        }).collect();
+        for address in &addresses {
+            cache.public_key(address).unwrap();
+        }
+        assert_eq!(cache.keys.len(), 2);
+        assert_eq!(cache.public_key(&addresses[2]).unwrap(), cache.public_key(&addresses[2]).unwrap());
+        assert!(cache.public_key("not an address").is_err());
+        assert_eq!(cache.keys.len(), 2);
+    }
+}
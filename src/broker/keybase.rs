@@ -12,6 +12,7 @@ use grin_core::libtx::slate::Slate;
 use common::{Error, Wallet713Error};
 use contacts::{Address, KeybaseAddress};
 use super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason};
+use super::subscription::Subscription;
 
 pub const TOPIC_SLATE_NEW: &str = "grin_slate_new";
 pub const TOPIC_WALLET713_SLATES: &str = "wallet713_grin_slate";
@@ -46,7 +47,7 @@ impl KeybaseSubscriber {
 }
 
 impl Publisher for KeybasePublisher {
-    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<(), Error> {
+    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<Option<String>, Error> {
         let keybase_address = KeybaseAddress::from_str(&to.to_string()).unwrap();
 
         // make sure we don't send message with ttl to wallet713 as keybase oneshot does not support exploding lifetimes
@@ -60,15 +61,17 @@ impl Publisher for KeybasePublisher {
         } else {
             KeybaseBroker::send(&slate, &to.stripped(), TOPIC_WALLET713_SLATES, ttl)?;
         }
-        Ok(())
+        Ok(None)
     }
 }
 
 impl Subscriber for KeybaseSubscriber {
-    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<(), Error> {
+    // runs until stopped, so the subscription it returns has closed already
+    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<Subscription, Error> {
         if let Ok(mut guard) = self.stop_signal.lock() {
             *guard = false;
         }
+        let (subscription, handler) = Subscription::watch(Box::new(self.clone()), handler);
         let mut subscribed = false;
         let mut dropped = false;
         let result: Result<(), Error> = loop {
@@ -117,7 +120,7 @@ impl Subscriber for KeybaseSubscriber {
             Err(e) => handler.on_close(CloseReason::Abnormal(e)),
             _ => handler.on_close(CloseReason::Normal),
         }
-        Ok(())
+        Ok(subscription)
     }
 
     fn stop(&self) {
@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+
+use grin_core::libtx::slate::Slate;
+
+use common::{Error, Wallet713Error};
+use contacts::Address;
+
+use super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason};
+use super::subscription::Subscription;
+
+type SharedHandler = Arc<Mutex<Box<SubscriptionHandler + Send>>>;
+
+// in-memory publisher/subscriber pair. slates posted to a `MockPublisher` are handed straight
+// to the handler of the `MockSubscriber` it is linked to, no relay involved. handlers are called
+// without holding on to the subscriber, so they can reply through a linked publisher.
+#[derive(Clone)]
+pub struct MockSubscriber {
+    handler: Arc<Mutex<Option<SharedHandler>>>,
+}
+
+impl MockSubscriber {
+    pub fn new() -> Self {
+        Self {
+            handler: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn deliver(&self, from: &Address, slate: &Slate) -> Result<(), Error> {
+        let handler = self.handler.lock().unwrap().clone();
+        match handler {
+            Some(handler) => {
+                let mut slate = slate.clone();
+                handler.lock().unwrap().on_slate(from, &mut slate);
+                Ok(())
+            },
+            None => Err(Wallet713Error::ClosedListener("mock".to_string()))?,
+        }
+    }
+}
+
+impl Subscriber for MockSubscriber {
+    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<Subscription, Error> {
+        let (subscription, handler) = Subscription::watch(Box::new(self.clone()), handler);
+        let handler = Arc::new(Mutex::new(handler));
+        // slates posted meanwhile wait for `on_open` to return
+        let opening = handler.lock().unwrap();
+        {
+            let mut guard = self.handler.lock().unwrap();
+            if guard.is_some() {
+                Err(Wallet713Error::AlreadySubscribed)?;
+            }
+            *guard = Some(handler.clone());
+        }
+        opening.on_open();
+        Ok(subscription)
+    }
+
+    fn stop(&self) {
+        let handler = self.handler.lock().unwrap().take();
+        if let Some(handler) = handler {
+            handler.lock().unwrap().on_close(CloseReason::Normal);
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.handler.lock().unwrap().is_some()
+    }
+}
+
+// for tests that only care about the subscription, not what it receives
+pub struct NoopHandler;
+
+impl SubscriptionHandler for NoopHandler {
+    fn on_open(&self) {}
+    fn on_slate(&self, _from: &Address, _slate: &mut Slate) {}
+    fn on_close(&self, _result: CloseReason) {}
+    fn on_dropped(&self) {}
+    fn on_reestablished(&self) {}
+}
+
+pub struct MockPublisher {
+    from: Box<Address + Send>,
+    subscriber: MockSubscriber,
+}
+
+impl MockPublisher {
+    pub fn new(from: Box<Address + Send>, subscriber: &MockSubscriber) -> Self {
+        Self {
+            from,
+            subscriber: subscriber.clone(),
+        }
+    }
+}
+
+impl Publisher for MockPublisher {
+    fn post_slate(&self, slate: &Slate, _to: &Address) -> Result<Option<String>, Error> {
+        self.subscriber.deliver(&*self.from, slate)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use grin_core::libtx::slate::Slate;
+    use uuid::Uuid;
+
+    use contacts::{Address, GrinboxAddress};
+    use super::{MockPublisher, MockSubscriber};
+    use super::super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason};
+
+    const ADDRESS: &str = "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU";
+
+    struct RecordingHandler {
+        received: Arc<Mutex<Vec<(String, Uuid)>>>,
+    }
+
+    impl SubscriptionHandler for RecordingHandler {
+        fn on_open(&self) {}
+        fn on_slate(&self, from: &Address, slate: &mut Slate) {
+            self.received.lock().unwrap().push((from.to_string(), slate.id));
+        }
+        fn on_close(&self, _result: CloseReason) {}
+        fn on_dropped(&self) {}
+        fn on_reestablished(&self) {}
+    }
+
+    // answers every slate through a publisher linked back to the sender
+    struct ReplyingHandler {
+        subscriber: MockSubscriber,
+        reply: MockPublisher,
+    }
+
+    impl SubscriptionHandler for ReplyingHandler {
+        fn on_open(&self) {
+            assert!(self.subscriber.is_running());
+        }
+        fn on_slate(&self, from: &Address, slate: &mut Slate) {
+            assert!(self.subscriber.is_running());
+            self.reply.post_slate(slate, from).unwrap();
+        }
+        fn on_close(&self, _result: CloseReason) {
+            assert!(!self.subscriber.is_running());
+        }
+        fn on_dropped(&self) {}
+        fn on_reestablished(&self) {}
+    }
+
+    #[test]
+    fn can_reply_from_a_handler() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let from = GrinboxAddress::from_str(ADDRESS).unwrap();
+        let mut sender = MockSubscriber::new();
+        let mut receiver = MockSubscriber::new();
+        sender.start(Box::new(RecordingHandler { received: received.clone() })).unwrap();
+        receiver.start(Box::new(ReplyingHandler {
+            subscriber: receiver.clone(),
+            reply: MockPublisher::new(Box::new(from.clone()), &sender),
+        })).unwrap();
+
+        let slate = Slate::blank(2);
+        MockPublisher::new(Box::new(from.clone()), &receiver).post_slate(&slate, &from).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![(ADDRESS.to_string(), slate.id)]);
+        receiver.stop();
+        sender.stop();
+    }
+
+    #[test]
+    fn can_post_slate_to_mock_subscriber() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut subscriber = MockSubscriber::new();
+        let from = GrinboxAddress::from_str(ADDRESS).unwrap();
+        let publisher = MockPublisher::new(Box::new(from.clone()), &subscriber);
+        let slate = Slate::blank(2);
+
+        assert!(publisher.post_slate(&slate, &from).is_err());
+
+        subscriber.start(Box::new(RecordingHandler { received: received.clone() })).unwrap();
+        assert!(subscriber.is_running());
+        publisher.post_slate(&slate, &from).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![(ADDRESS.to_string(), slate.id)]);
+
+        subscriber.stop();
+        assert!(!subscriber.is_running());
+    }
+}
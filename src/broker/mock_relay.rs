@@ -0,0 +1,617 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use mio::tcp::TcpStream;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod, SslStream};
+use openssl::x509::{X509, X509NameBuilder};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use serde_json;
+use uuid::Uuid;
+use ws::{Builder, Settings, Sender, Handler, Handshake, Message, CloseCode, Result as WsResult};
+
+use common::crypto::{SecretKey, Signature, Hex, verify_signature};
+use contacts::{Address, GrinboxAddress};
+
+use super::config::BrokerConfig;
+use super::protocol::{ProtocolRequest, ProtocolResponse, ProtocolError};
+
+const DOMAIN: &str = "localhost";
+
+// a slate the relay took in, as it is handed on to the recipient
+struct RelayedSlate {
+    from: String,
+    str: String,
+    signature: String,
+    challenge: String,
+    compressed: bool,
+    memo: Option<String>,
+}
+
+#[derive(Default)]
+struct RelayState {
+    // the connection and the last sequence number handed out, by subscribed public key
+    subscriptions: HashMap<String, (Sender, u64)>,
+    // slates for recipients that were not subscribed when they arrived
+    queued: HashMap<String, Vec<RelayedSlate>>,
+    // sender and recipient of every post that was accepted
+    posted: Vec<(String, String)>,
+    // public keys that are turned away when they subscribe
+    banned: Vec<String>,
+    next_id: u64,
+}
+
+// a grinbox relay on localhost for tests. it speaks enough of the protocol for subscribers and
+// publishers to go through challenge, subscribe, post and delivery, checking every signature
+// along the way, over tls with a certificate of its own.
+pub struct MockRelay {
+    port: u16,
+    ca_bundle: String,
+    state: Arc<Mutex<RelayState>>,
+    broadcaster: Sender,
+}
+
+impl MockRelay {
+    // listens on a port the os picks, so tests running at the same time never collide
+    pub fn start() -> Self {
+        let (key, cert) = self_signed_certificate();
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        let acceptor = Arc::new(acceptor.build());
+
+        let state = Arc::new(Mutex::new(RelayState::default()));
+        let cloned_state = state.clone();
+        let (bound, is_bound) = channel();
+        thread::spawn(move || {
+            let settings = Settings { encrypt_server: true, ..Settings::default() };
+            let server = Builder::new().with_settings(settings).build(move |out: Sender| RelayConnection {
+                out,
+                challenge: Uuid::new_v4().to_string(),
+                state: cloned_state.clone(),
+                acceptor: acceptor.clone(),
+            }).unwrap().bind(("127.0.0.1", 0)).unwrap();
+            bound.send((server.broadcaster(), server.local_addr().unwrap().port())).unwrap();
+            server.run().is_ok();
+        });
+        let (broadcaster, port) = is_bound.recv().unwrap();
+
+        fs::create_dir_all("./target/tests").unwrap();
+        let ca_bundle = format!("./target/tests/mock-relay-{}.pem", port);
+        File::create(&ca_bundle).unwrap().write_all(&cert.to_pem().unwrap()).unwrap();
+
+        Self {
+            port,
+            ca_bundle,
+            state,
+            broadcaster,
+        }
+    }
+
+    // where `secret_key` receives slates on this relay
+    pub fn address(&self, secret_key: &SecretKey) -> GrinboxAddress {
+        GrinboxAddress::from_secret_key(secret_key, DOMAIN.to_string(), Some(self.port)).unwrap()
+    }
+
+    // trusts the certificate of this relay and nothing else
+    pub fn config(&self) -> BrokerConfig {
+        BrokerConfig {
+            ca_bundle: Some(self.ca_bundle.clone()),
+            ..BrokerConfig::default()
+        }
+    }
+
+    pub fn is_subscribed(&self, address: &GrinboxAddress) -> bool {
+        self.state.lock().unwrap().subscriptions.contains_key(&address.public_key)
+    }
+
+    pub fn posted(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().posted.clone()
+    }
+
+    pub fn ban(&self, address: &GrinboxAddress) {
+        self.state.lock().unwrap().banned.push(address.public_key.clone());
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        self.broadcaster.shutdown().is_ok();
+    }
+}
+
+fn self_signed_certificate() -> (PKey<Private>, X509) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", DOMAIN).unwrap();
+    let name = name.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+    let alt_name = SubjectAlternativeName::new().dns(DOMAIN).build(&builder.x509v3_context(None, None)).unwrap();
+    builder.append_extension(alt_name).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    (key, builder.build())
+}
+
+fn verified(address: &str, message: &str, signature: &str) -> bool {
+    let public_key = match GrinboxAddress::from_str(address).and_then(|address| address.public_key()) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    match Signature::from_hex(signature) {
+        Ok(signature) => verify_signature(message, &signature, &public_key).is_ok(),
+        Err(_) => false,
+    }
+}
+
+struct RelayConnection {
+    out: Sender,
+    challenge: String,
+    state: Arc<Mutex<RelayState>>,
+    acceptor: Arc<SslAcceptor>,
+}
+
+impl RelayConnection {
+    fn respond(&self, response: &ProtocolResponse) -> WsResult<()> {
+        self.out.send(serde_json::to_string(response).unwrap())
+    }
+
+    fn reject(&self, kind: ProtocolError) -> WsResult<()> {
+        let description = kind.to_string();
+        self.respond(&ProtocolResponse::Error { kind, description })
+    }
+
+    fn send_challenge(&self) -> WsResult<()> {
+        self.respond(&ProtocolResponse::Challenge {
+            str: self.challenge.clone(),
+            capabilities: vec!["bound-encryption".to_string()],
+            public_key: None,
+            timestamp: None,
+        })
+    }
+
+    fn deliver(out: &Sender, sequence: u64, slate: RelayedSlate) -> WsResult<()> {
+        let response = ProtocolResponse::Slate {
+            from: slate.from,
+            str: slate.str,
+            signature: slate.signature,
+            challenge: slate.challenge,
+            received_at: None,
+            relay: None,
+            compressed: slate.compressed,
+            sequence: Some(sequence),
+            memo: slate.memo,
+        };
+        out.send(serde_json::to_string(&response).unwrap())
+    }
+
+    fn subscribe(&self, address: String, signature: String) -> WsResult<()> {
+        if !verified(&address, &self.challenge, &signature) {
+            return self.reject(ProtocolError::InvalidSignature);
+        }
+        if self.state.lock().unwrap().banned.contains(&address) {
+            return self.reject(ProtocolError::Banned);
+        }
+        self.respond(&ProtocolResponse::Ok { id: None })?;
+        let mut state = self.state.lock().unwrap();
+        let queued = state.queued.remove(&address).unwrap_or_default();
+        let mut sequence = 0;
+        for slate in queued {
+            sequence += 1;
+            RelayConnection::deliver(&self.out, sequence, slate)?;
+        }
+        state.subscriptions.insert(address, (self.out.clone(), sequence));
+        Ok(())
+    }
+
+    fn unsubscribe(&self, address: String, signature: String) -> WsResult<()> {
+        if !verified(&address, &self.challenge, &signature) {
+            return self.reject(ProtocolError::InvalidSignature);
+        }
+        self.state.lock().unwrap().subscriptions.remove(&address);
+        self.respond(&ProtocolResponse::Ok { id: None })
+    }
+
+    fn post_slate(&self, from: String, to: String, str: String, signature: String, confirm_delivery: bool, compressed: bool, memo: Option<String>) -> WsResult<()> {
+        let signed = match memo {
+            Some(ref memo) => format!("{}\n{}\n{}", str, memo, self.challenge),
+            None => format!("{}{}", str, self.challenge),
+        };
+        if !verified(&from, &signed, &signature) {
+            return self.reject(ProtocolError::InvalidSignature);
+        }
+        let slate = RelayedSlate { from: from.clone(), str, signature, challenge: self.challenge.clone(), compressed, memo };
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.next_id += 1;
+        let id = format!("slate-{}", state.next_id);
+        state.posted.push((from, to.clone()));
+        let delivered = match state.subscriptions.get_mut(&to) {
+            Some(&mut (ref out, ref mut sequence)) => {
+                *sequence += 1;
+                RelayConnection::deliver(out, *sequence, slate)?;
+                true
+            },
+            None => {
+                state.queued.entry(to.clone()).or_insert_with(Vec::new).push(slate);
+                false
+            },
+        };
+        self.respond(&ProtocolResponse::Ok { id: Some(id.clone()) })?;
+        if confirm_delivery && delivered {
+            self.respond(&ProtocolResponse::Delivered { to, id: Some(id) })?;
+        }
+        Ok(())
+    }
+}
+
+impl Handler for RelayConnection {
+    fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+        self.send_challenge()
+    }
+
+    fn on_message(&mut self, msg: Message) -> WsResult<()> {
+        let request = match serde_json::from_str::<ProtocolRequest>(&msg.to_string()) {
+            Ok(x) => x,
+            Err(_) => return self.reject(ProtocolError::InvalidRequest),
+        };
+        match request {
+            ProtocolRequest::Challenge => {
+                self.challenge = Uuid::new_v4().to_string();
+                self.send_challenge()
+            },
+            ProtocolRequest::Subscribe { address, signature, .. } => self.subscribe(address, signature),
+            ProtocolRequest::Unsubscribe { address, signature } => self.unsubscribe(address, signature),
+            ProtocolRequest::PostSlate { from, to, str, signature, confirm_delivery, compressed, memo } => {
+                self.post_slate(from, to, str, signature, confirm_delivery, compressed, memo)
+            },
+            ProtocolRequest::IsOnline { address } => {
+                let online = self.state.lock().unwrap().subscriptions.contains_key(&address);
+                self.respond(&ProtocolResponse::Presence { online, slate_versions: vec![] })
+            },
+            _ => self.reject(ProtocolError::InvalidRequest),
+        }
+    }
+
+    fn on_close(&mut self, _code: CloseCode, _reason: &str) {
+        let token = self.out.token();
+        self.state.lock().unwrap().subscriptions.retain(|_, &mut (ref out, _)| out.token() != token);
+    }
+
+    fn upgrade_ssl_server(&mut self, sock: TcpStream) -> WsResult<SslStream<TcpStream>> {
+        self.acceptor.accept(sock).map_err(From::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::thread;
+    use std::time::Duration;
+    use grin_core::libtx::slate::Slate;
+
+    use common::{Error, Wallet713Error};
+    use common::crypto::{SecretKey, generate_secret_key};
+    use contacts::{Address, GrinboxAddress};
+    use super::{MockRelay, DOMAIN};
+    use super::super::codec::{SlateCodec, JsonCodec};
+    use super::super::grinbox::{GrinboxPublisher, GrinboxSubscriber};
+    use super::super::mock::NoopHandler;
+    use super::super::resolver::AddressResolver;
+    use super::super::signer::{AddressKeySource, KeySigner};
+    use super::super::types::{Publisher, Subscriber, SubscriptionHandler, DeliveryGuarantee, SlateMeta, CloseReason, PostAuditor, PostRecord, SlateTransform};
+
+    struct Keys(Vec<SecretKey>);
+
+    impl AddressKeySource for Keys {
+        fn address_key(&self, index: u32) -> Result<SecretKey, Error> {
+            Ok(self.0[index as usize].clone())
+        }
+    }
+
+    // answers every slate back to whoever sent it
+    struct Replier {
+        publisher: GrinboxPublisher,
+    }
+
+    impl SubscriptionHandler for Replier {
+        fn on_open(&self) {}
+
+        fn on_slate(&self, from: &Address, slate: &mut Slate) {
+            self.publisher.post_slate(slate, from).unwrap();
+        }
+
+        fn on_close(&self, _result: CloseReason) {}
+
+        fn on_dropped(&self) {}
+
+        fn on_reestablished(&self) {}
+    }
+
+    struct Contacts(GrinboxAddress);
+
+    impl AddressResolver for Contacts {
+        fn resolve(&self, name: &str) -> Result<GrinboxAddress, Error> {
+            match name {
+                "bob" => Ok(self.0.clone()),
+                _ => Err(Wallet713Error::AddressResolutionFailed(name.to_string()).into()),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Audit(Mutex<Vec<PostRecord>>);
+
+    impl PostAuditor for Audit {
+        fn on_posted(&self, record: &PostRecord) {
+            self.0.lock().unwrap().push(record.clone());
+        }
+    }
+
+    struct Amount(u64);
+
+    impl SlateTransform for Amount {
+        fn transform(&self, _from: &Address, mut slate: Slate) -> Result<Slate, Error> {
+            slate.amount = self.0;
+            Ok(slate)
+        }
+    }
+
+    struct CountingCodec {
+        inner: JsonCodec,
+        used: AtomicUsize,
+    }
+
+    impl SlateCodec for CountingCodec {
+        fn encode(&self, slate: &Slate) -> Result<String, Error> {
+            self.used.fetch_add(1, Ordering::SeqCst);
+            self.inner.encode(slate)
+        }
+
+        fn decode(&self, slate_str: &str) -> Result<Slate, Error> {
+            self.used.fetch_add(1, Ordering::SeqCst);
+            self.inner.decode(slate_str)
+        }
+    }
+
+    #[test]
+    fn relays_queued_slates() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let slate = Slate::blank(2);
+        assert!(publisher.post_slate_with_guarantee(&slate, &receiver, DeliveryGuarantee::Acknowledged).unwrap().is_some());
+
+        // encrypted for the recipient and signed by the sender all the way through
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        let (from, received) = subscriber.receive_one(Duration::from_secs(10)).unwrap();
+        assert_eq!(from.public_key, sender.public_key);
+        assert_eq!(received.id, slate.id);
+        assert_eq!(relay.posted(), vec![(sender.stripped(), receiver.public_key.clone())]);
+    }
+
+    #[test]
+    fn confirms_delivery_to_subscribers() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        let receiving = thread::spawn(move || subscriber.receive_one(Duration::from_secs(10)));
+        for _ in 0..100 {
+            if relay.is_subscribed(&receiver) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let slate = Slate::blank(2);
+        assert!(publisher.post_slate_with_guarantee(&slate, &receiver, DeliveryGuarantee::Delivered).is_ok());
+        assert_eq!(receiving.join().unwrap().unwrap().1.id, slate.id);
+    }
+
+    #[test]
+    fn carries_memos() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let slate = Slate::blank(2);
+        assert!(publisher.post_slate_with_memo(&slate, &receiver, &"x".repeat(257), DeliveryGuarantee::Acknowledged).is_err());
+        publisher.post_slate_with_memo(&slate, &receiver, "invoice 42", DeliveryGuarantee::Acknowledged).unwrap();
+
+        let (memos, received) = channel();
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        subscriber.observe(Box::new(move |_from: &GrinboxAddress, _slate: &Slate, meta: &SlateMeta| memos.send(meta.memo.clone()).unwrap())).unwrap();
+        assert_eq!(received.recv_timeout(Duration::from_secs(10)).unwrap(), Some("invoice 42".to_string()));
+        subscriber.stop();
+    }
+
+    #[test]
+    fn streams_slates_until_stopped() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let slates = vec![Slate::blank(2), Slate::blank(2)];
+        for slate in &slates {
+            publisher.post_slate_with_guarantee(slate, &receiver, DeliveryGuarantee::Acknowledged).unwrap();
+        }
+
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        let received = subscriber.receiver().unwrap();
+        for slate in &slates {
+            let (from, received) = received.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(from.public_key, sender.public_key);
+            assert_eq!(received.id, slate.id);
+        }
+        subscriber.stop();
+        match received.recv_timeout(Duration::from_secs(10)) {
+            Err(RecvTimeoutError::Disconnected) => {},
+            _ => panic!("the channel was not closed with the subscription"),
+        }
+    }
+
+    #[test]
+    fn ends_a_banned_subscription() {
+        let relay = MockRelay::start();
+        let key = generate_secret_key().unwrap();
+        let address = relay.address(&key);
+        relay.ban(&address);
+        let mut subscriber = GrinboxSubscriber::with_config(&address, &key, relay.config()).unwrap();
+        let error = subscriber.receive_one(Duration::from_secs(10)).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::RelayClosed(_)) => {},
+            _ => panic!("unexpected error: {}", error),
+        }
+        match subscriber.last_close_reason() {
+            Some(CloseReason::ProtocolError(description)) => assert_eq!(description, "banned!"),
+            _ => panic!("the subscription did not end with the error of the relay"),
+        }
+    }
+
+    #[test]
+    fn rejects_forged_posts() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let mut publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        publisher.set_challenge_signer(Arc::new(KeySigner::new(&generate_secret_key().unwrap())));
+        assert!(publisher.post_slate_with_guarantee(&Slate::blank(2), &receiver, DeliveryGuarantee::Acknowledged).is_err());
+        assert!(relay.posted().is_empty());
+    }
+
+    #[test]
+    fn uses_derived_addresses() {
+        let relay = MockRelay::start();
+        let keys = Keys(vec![generate_secret_key().unwrap(), generate_secret_key().unwrap()]);
+        let (sender, receiver) = (relay.address(&keys.0[0]), relay.address(&keys.0[1]));
+        let publisher = GrinboxPublisher::with_derived_key(&keys, 0, DOMAIN, Some(relay.port), relay.config()).unwrap();
+        let mut subscriber = GrinboxSubscriber::with_derived_key(&keys, 1, DOMAIN, Some(relay.port), relay.config()).unwrap();
+        assert_eq!(publisher.address(), &sender);
+        assert_eq!(subscriber.address(), &receiver);
+        assert_eq!(publisher.config().ca_bundle, relay.config().ca_bundle);
+        assert_eq!(subscriber.config().ca_bundle, relay.config().ca_bundle);
+
+        let slate = Slate::blank(2);
+        publisher.post_slate_with_guarantee(&slate, &receiver, DeliveryGuarantee::Acknowledged).unwrap();
+        subscriber.set_challenge_signer(Arc::new(KeySigner::new(&keys.0[1])));
+        let (from, received) = subscriber.receive_one(Duration::from_secs(10)).unwrap();
+        assert_eq!(from.public_key, sender.public_key);
+        assert_eq!(received.id, slate.id);
+    }
+
+    #[test]
+    fn awaits_the_response() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        let publisher = GrinboxPublisher::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        subscriber.start(Box::new(Replier { publisher })).unwrap();
+
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let slate = Slate::blank(2);
+        let response = publisher.send_and_await_response(&slate, &receiver, Duration::from_secs(10)).unwrap();
+        assert_eq!(response.id, slate.id);
+        subscriber.stop();
+    }
+
+    #[test]
+    fn posts_in_the_background() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let posting = publisher.post_slate_async(&Slate::blank(2), &receiver, DeliveryGuarantee::Acknowledged);
+        assert!(posting.join().unwrap().unwrap().is_some());
+        assert_eq!(relay.posted(), vec![(sender.stripped(), receiver.public_key.clone())]);
+    }
+
+    #[test]
+    fn resolves_and_audits_posts() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let mut publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        let audit = Arc::new(Audit::default());
+        publisher.set_address_resolver(Arc::new(Contacts(receiver.clone())));
+        publisher.set_post_auditor(audit.clone());
+
+        let slate = Slate::blank(2);
+        publisher.post_slate_to(&slate, "bob").unwrap();
+        assert!(publisher.post_slate_to(&slate, "carol").is_err());
+        let posted = audit.0.lock().unwrap();
+        assert_eq!(posted.len(), 1);
+        assert_eq!(posted[0].slate_id, slate.id.to_string());
+        assert_eq!(posted[0].recipient, receiver.stripped());
+        assert!(!posted[0].loopback);
+    }
+
+    #[test]
+    fn decodes_and_transforms_incoming_slates() {
+        let relay = MockRelay::start();
+        let (sender_key, receiver_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (sender, receiver) = (relay.address(&sender_key), relay.address(&receiver_key));
+        let codec = Arc::new(CountingCodec { inner: JsonCodec::new(None), used: AtomicUsize::new(0) });
+        let mut publisher = GrinboxPublisher::with_config(&sender, &sender_key, relay.config()).unwrap();
+        publisher.set_slate_codec(codec.clone());
+        publisher.post_slate_with_guarantee(&Slate::blank(2), &receiver, DeliveryGuarantee::Acknowledged).unwrap();
+        let encoded = codec.used.load(Ordering::SeqCst);
+        assert!(encoded > 0);
+
+        let mut subscriber = GrinboxSubscriber::with_config(&receiver, &receiver_key, relay.config()).unwrap();
+        subscriber.set_slate_codec(codec.clone());
+        subscriber.set_slate_transform(Arc::new(Amount(42)));
+        let (_, received) = subscriber.receive_one(Duration::from_secs(10)).unwrap();
+        assert_eq!(received.amount, 42);
+        assert!(codec.used.load(Ordering::SeqCst) > encoded);
+    }
+
+    #[test]
+    fn rotates_the_key_of_a_running_subscription() {
+        let relay = MockRelay::start();
+        let (old_key, new_key) = (generate_secret_key().unwrap(), generate_secret_key().unwrap());
+        let (old, new) = (relay.address(&old_key), relay.address(&new_key));
+        let mut subscriber = GrinboxSubscriber::with_config(&old, &old_key, relay.config()).unwrap();
+        subscriber.start(Box::new(NoopHandler)).unwrap();
+        for _ in 0..100 {
+            if relay.is_subscribed(&old) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(subscriber.supports_capability("bound-encryption"));
+        assert!(!subscriber.supports_capability("compression"));
+
+        subscriber.rotate_key(new_key).unwrap();
+        for _ in 0..100 {
+            if relay.is_subscribed(&new) && !relay.is_subscribed(&old) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(relay.is_subscribed(&new));
+        assert!(!relay.is_subscribed(&old));
+        assert_eq!(subscriber.address(), &new);
+        subscriber.stop();
+    }
+}
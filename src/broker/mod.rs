@@ -2,7 +2,45 @@ mod types;
 mod keybase;
 mod grinbox;
 mod protocol;
+mod config;
+mod slate_version;
+mod compression;
+mod codec;
+mod tls;
+mod signer;
+mod rate_limit;
+mod validation;
+mod clock;
+mod webhook;
+mod resolver;
+mod correlation;
+mod proxy;
+mod key_cache;
+mod sender_filter;
+mod state;
+mod subscription;
+// only the tests listen on several addresses at once so far
+#[cfg(test)]
+mod multi;
+#[cfg(test)]
+mod dispatch;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod mock_relay;
 
-pub use self::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason};
+pub use self::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason, BrokerStatus, ConnectionState, SlateMeta, DeliveryGuarantee, PostRecord, PendingPost, PostAuditor, SlateTransform};
 pub use self::keybase::{KeybasePublisher, KeybaseSubscriber, TOPIC_SLATE_NEW};
-pub use self::grinbox::{GrinboxPublisher, GrinboxSubscriber};
+pub use self::grinbox::{GrinboxPublisher, GrinboxSubscriber, relay_port};
+pub use self::webhook::WebhookHandler;
+pub use self::signer::{ChallengeSigner, KeySigner, AddressKeySource};
+pub use self::resolver::{AddressResolver, RawAddressResolver};
+pub use self::sender_filter::SenderFilter;
+pub use self::correlation::{CorrelationStore, Correlation, SlateDirection, SlateState, SavedCorrelation};
+pub use self::state::{BrokerState, SavedPost};
+pub use self::subscription::Subscription;
+pub use self::slate_version::supported_slate_versions;
+pub use self::codec::{SlateCodec, JsonCodec};
+pub use self::clock::{Clock, SystemClock};
+pub use self::config::{BrokerConfig, CapabilityDowngradePolicy, FromAddressPolicy, SelfSendPolicy, RateLimitPolicy, TlsVersion};
+pub use self::proxy::ProxyUrl;
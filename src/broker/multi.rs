@@ -0,0 +1,311 @@
+use std::sync::{Arc, Mutex};
+
+use grin_core::libtx::slate::Slate;
+
+use common::Error;
+use contacts::{Address, GrinboxAddress};
+
+use super::types::{Subscriber, SubscriptionHandler, CloseReason, BrokerStatus, ConnectionState, SlateMeta};
+use super::grinbox::GrinboxSubscriber;
+use super::dispatch::DispatchPool;
+use super::subscription::Subscription;
+
+// listens on several grinbox addresses at once, feeding every slate into the same handler.
+// the receiving address is passed to the handler through `SlateMeta::recipient`.
+#[derive(Clone)]
+pub struct MultiSubscriber {
+    subscribers: Vec<GrinboxSubscriber>,
+    pool: Option<Arc<DispatchPool>>,
+}
+
+impl MultiSubscriber {
+    pub fn new(subscribers: Vec<GrinboxSubscriber>) -> Self {
+        Self {
+            subscribers,
+            pool: None,
+        }
+    }
+
+    // slates passed to `start_concurrent` are handled on up to `threads` threads at once
+    pub fn with_dispatch_threads(subscribers: Vec<GrinboxSubscriber>, threads: usize) -> Self {
+        Self {
+            subscribers,
+            pool: Some(Arc::new(DispatchPool::new(threads))),
+        }
+    }
+
+    // unlike `start`, does not wait for one slate to be handled before the next one is. the
+    // handler works on a copy of each slate, what it changes is not seen by the subscriber.
+    pub fn start_concurrent(&mut self, handler: Arc<SubscriptionHandler + Sync>) -> Result<Subscription, Error> {
+        let mut subscriptions = Vec::new();
+        for subscriber in self.subscribers.iter_mut() {
+            subscriptions.push(subscriber.start(Box::new(PooledHandler { inner: handler.clone(), pool: self.pool.clone() }))?);
+        }
+        Ok(Subscription::combine(Box::new(self.clone()), subscriptions))
+    }
+
+    // every address that is still subscribed, or trying to get back to its relay
+    pub fn active_subscriptions(&self) -> Vec<(GrinboxAddress, ConnectionState)> {
+        self.subscribers.iter()
+            .map(|subscriber| (subscriber.address().clone(), subscriber.connection_state()))
+            .filter(|&(_, state)| state != ConnectionState::Stopped)
+            .collect()
+    }
+}
+
+impl Subscriber for MultiSubscriber {
+    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<Subscription, Error> {
+        let handler = Arc::new(Mutex::new(handler));
+        let mut subscriptions = Vec::new();
+        for subscriber in self.subscribers.iter_mut() {
+            subscriptions.push(subscriber.start(Box::new(SharedHandler { inner: handler.clone() }))?);
+        }
+        Ok(Subscription::combine(Box::new(self.clone()), subscriptions))
+    }
+
+    fn stop(&self) {
+        for subscriber in &self.subscribers {
+            subscriber.stop();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.subscribers.iter().any(|subscriber| subscriber.is_running())
+    }
+
+    // all addresses together, connected since the first of them got connected
+    fn status(&self) -> BrokerStatus {
+        self.subscribers.iter().map(|subscriber| subscriber.status()).fold(BrokerStatus::default(), |total, status| BrokerStatus {
+            connected_since: total.connected_since.into_iter().chain(status.connected_since).min(),
+            reconnects: total.reconnects + status.reconnects,
+            last_slate_at: total.last_slate_at.max(status.last_slate_at),
+            pending_slates: total.pending_slates + status.pending_slates,
+            peak_pending_slates: total.peak_pending_slates.max(status.peak_pending_slates),
+            queue_capacity: total.queue_capacity + status.queue_capacity,
+            backpressure_waits: total.backpressure_waits + status.backpressure_waits,
+            dropped_slates: total.dropped_slates + status.dropped_slates,
+        })
+    }
+}
+
+struct SharedHandler {
+    inner: Arc<Mutex<Box<SubscriptionHandler + Send>>>,
+}
+
+impl SubscriptionHandler for SharedHandler {
+    fn on_open(&self) {
+        self.inner.lock().unwrap().on_open();
+    }
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        self.inner.lock().unwrap().on_slate(from, slate);
+    }
+
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, meta: &SlateMeta) {
+        self.inner.lock().unwrap().on_slate_with_meta(from, slate, meta);
+    }
+
+    fn on_close(&self, result: CloseReason) {
+        self.inner.lock().unwrap().on_close(result);
+    }
+
+    fn on_dropped(&self) {
+        self.inner.lock().unwrap().on_dropped();
+    }
+
+    fn on_reestablished(&self) {
+        self.inner.lock().unwrap().on_reestablished();
+    }
+
+    fn on_reconnecting(&self, relay: &str) {
+        self.inner.lock().unwrap().on_reconnecting(relay);
+    }
+
+    fn on_slate_version_unsupported(&self, from: &Address, version: u64) {
+        self.inner.lock().unwrap().on_slate_version_unsupported(from, version);
+    }
+
+    fn on_capability_downgrade(&self, lost: Vec<String>) {
+        self.inner.lock().unwrap().on_capability_downgrade(lost);
+    }
+
+    fn on_channel_rebound(&self) {
+        self.inner.lock().unwrap().on_channel_rebound();
+    }
+
+    fn on_unverified_slate(&self, from: &str, raw: &str) {
+        self.inner.lock().unwrap().on_unverified_slate(from, raw);
+    }
+
+    fn ordered_per_sender(&self) -> bool {
+        self.inner.lock().unwrap().ordered_per_sender()
+    }
+}
+
+struct PooledHandler {
+    inner: Arc<SubscriptionHandler + Sync>,
+    pool: Option<Arc<DispatchPool>>,
+}
+
+impl SubscriptionHandler for PooledHandler {
+    fn on_open(&self) {
+        self.inner.on_open();
+    }
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        self.on_slate_with_meta(from, slate, &SlateMeta::default());
+    }
+
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, meta: &SlateMeta) {
+        let pool = match self.pool {
+            Some(ref pool) => pool,
+            None => return self.inner.on_slate_with_meta(from, slate, meta),
+        };
+        let sender = from.to_string();
+        let key = match self.inner.ordered_per_sender() {
+            true => Some(sender.as_str()),
+            false => None,
+        };
+        let from = match GrinboxAddress::from_address(from) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("could not parse address `{}`: {}", sender, e);
+                return;
+            },
+        };
+        let inner = self.inner.clone();
+        let mut slate = slate.clone();
+        let meta = meta.clone();
+        pool.dispatch(key, Box::new(move || {
+            inner.on_slate_with_meta(&from, &mut slate, &meta);
+        }));
+    }
+
+    fn on_close(&self, result: CloseReason) {
+        self.inner.on_close(result);
+    }
+
+    fn on_dropped(&self) {
+        self.inner.on_dropped();
+    }
+
+    fn on_reestablished(&self) {
+        self.inner.on_reestablished();
+    }
+
+    fn on_reconnecting(&self, relay: &str) {
+        self.inner.on_reconnecting(relay);
+    }
+
+    fn on_slate_version_unsupported(&self, from: &Address, version: u64) {
+        self.inner.on_slate_version_unsupported(from, version);
+    }
+
+    fn on_capability_downgrade(&self, lost: Vec<String>) {
+        self.inner.on_capability_downgrade(lost);
+    }
+
+    fn on_channel_rebound(&self) {
+        self.inner.on_channel_rebound();
+    }
+
+    fn on_unverified_slate(&self, from: &str, raw: &str) {
+        self.inner.on_unverified_slate(from, raw);
+    }
+
+    fn ordered_per_sender(&self) -> bool {
+        self.inner.ordered_per_sender()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread;
+    use std::time::Duration;
+    use grin_core::libtx::slate::Slate;
+
+    use common::crypto::generate_secret_key;
+    use contacts::Address;
+    use super::MultiSubscriber;
+    use super::super::grinbox::{GrinboxPublisher, GrinboxSubscriber};
+    use super::super::mock::NoopHandler;
+    use super::super::mock_relay::MockRelay;
+    use super::super::types::{Publisher, Subscriber, SubscriptionHandler, CloseReason, ConnectionState, DeliveryGuarantee};
+
+    struct Forwarding(Mutex<Sender<String>>);
+
+    impl SubscriptionHandler for Forwarding {
+        fn on_open(&self) {}
+
+        fn on_slate(&self, _from: &Address, slate: &mut Slate) {
+            self.0.lock().unwrap().send(slate.id.to_string()).is_ok();
+        }
+
+        fn on_close(&self, _result: CloseReason) {}
+
+        fn on_dropped(&self) {}
+
+        fn on_reestablished(&self) {}
+    }
+
+    #[test]
+    fn lists_active_subscriptions() {
+        let relay = MockRelay::start();
+        let keys = vec![generate_secret_key().unwrap(), generate_secret_key().unwrap()];
+        let addresses: Vec<_> = keys.iter().map(|key| relay.address(key)).collect();
+        let subscribers = addresses.iter().zip(keys.iter())
+            .map(|(address, key)| GrinboxSubscriber::with_config(address, key, relay.config()).unwrap())
+            .collect();
+        let mut multi = MultiSubscriber::new(subscribers);
+        assert!(multi.active_subscriptions().is_empty());
+
+        multi.start(Box::new(NoopHandler)).unwrap();
+        for _ in 0..100 {
+            if addresses.iter().all(|address| relay.is_subscribed(address)) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let active = multi.active_subscriptions();
+        assert_eq!(active.len(), 2);
+        for (&(ref address, state), expected) in active.iter().zip(addresses.iter()) {
+            assert_eq!(address.public_key, expected.public_key);
+            assert_eq!(state, ConnectionState::Connected);
+        }
+
+        multi.stop();
+        assert!(multi.active_subscriptions().is_empty());
+    }
+
+    #[test]
+    fn handles_slates_on_the_dispatch_threads() {
+        let relay = MockRelay::start();
+        let keys = vec![generate_secret_key().unwrap(), generate_secret_key().unwrap()];
+        let addresses: Vec<_> = keys.iter().map(|key| relay.address(key)).collect();
+        let sender_key = generate_secret_key().unwrap();
+        let publisher = GrinboxPublisher::with_config(&relay.address(&sender_key), &sender_key, relay.config()).unwrap();
+        let mut slate_ids = Vec::new();
+        for address in &addresses {
+            let slate = Slate::blank(2);
+            publisher.post_slate_with_guarantee(&slate, address, DeliveryGuarantee::Acknowledged).unwrap();
+            slate_ids.push(slate.id.to_string());
+        }
+
+        let subscribers = addresses.iter().zip(keys.iter())
+            .map(|(address, key)| GrinboxSubscriber::with_config(address, key, relay.config()).unwrap())
+            .collect();
+        let mut multi = MultiSubscriber::with_dispatch_threads(subscribers, 2);
+        let (sender, receiver) = channel();
+        multi.start_concurrent(Arc::new(Forwarding(Mutex::new(sender)))).unwrap();
+        let mut received = vec![
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        ];
+        received.sort();
+        slate_ids.sort();
+        assert_eq!(received, slate_ids);
+        multi.stop();
+    }
+}
@@ -8,6 +8,18 @@ pub enum ProtocolError {
     InvalidSignature,
     InvalidChallenge,
     TooManySubscriptions,
+    Unauthorized,
+    Banned,
+}
+
+impl ProtocolError {
+    // after one of these nothing we could send on the same connection is going to be accepted
+    pub fn is_fatal(&self) -> bool {
+        match *self {
+            ProtocolError::TooManySubscriptions | ProtocolError::Unauthorized | ProtocolError::Banned => true,
+            ProtocolError::UnknownError | ProtocolError::InvalidRequest | ProtocolError::InvalidSignature | ProtocolError::InvalidChallenge => false,
+        }
+    }
 }
 
 impl Display for ProtocolError {
@@ -18,6 +30,8 @@ impl Display for ProtocolError {
             ProtocolError::InvalidSignature => write!(f, "{}", "invalid signature!"),
             ProtocolError::InvalidChallenge => write!(f, "{}", "invalid challenge!"),
             ProtocolError::TooManySubscriptions => write!(f, "{}", "too many subscriptions!"),
+            ProtocolError::Unauthorized => write!(f, "{}", "unauthorized!"),
+            ProtocolError::Banned => write!(f, "{}", "banned!"),
         }
     }
 }
@@ -26,18 +40,40 @@ impl Display for ProtocolError {
 #[serde(tag = "type")]
 pub enum ProtocolRequest {
     Challenge,
-    Subscribe { address: String, signature: String },
-    PostSlate { from: String, to: String, str: String, signature: String },
-    Unsubscribe { address: String },
+    Subscribe {
+        address: String,
+        signature: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        slate_versions: Vec<u64>,
+    },
+    Binding { address: String },
+    SecureSubscribe { public_key: String, message: String },
+    PostSlate {
+        from: String,
+        to: String,
+        str: String,
+        signature: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        confirm_delivery: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        compressed: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+    },
+    Unsubscribe { address: String, signature: String },
+    IsOnline { address: String },
 }
 
 impl Display for ProtocolRequest {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match *self {
             ProtocolRequest::Challenge => write!(f, "{}", "Challenge".bright_purple()),
-            ProtocolRequest::Subscribe { ref address, signature: _ } => write!(f, "{} to {}", "Subscribe".bright_purple(), address.bright_green()),
-            ProtocolRequest::Unsubscribe { ref address } => write!(f, "{} from {}", "Unsubscribe".bright_purple(), address.bright_green()),
-            ProtocolRequest::PostSlate { ref from, ref to, str: _, signature: _ } => write!(f, "{} from {} to {}", "PostSlate".bright_purple(), from.bright_green(), to.bright_green()),
+            ProtocolRequest::Subscribe { ref address, .. } => write!(f, "{} to {}", "Subscribe".bright_purple(), address.bright_green()),
+            ProtocolRequest::Binding { ref address } => write!(f, "{} for {}", "Binding".bright_purple(), address.bright_green()),
+            ProtocolRequest::SecureSubscribe { .. } => write!(f, "{}", "SecureSubscribe".bright_purple()),
+            ProtocolRequest::Unsubscribe { ref address, .. } => write!(f, "{} from {}", "Unsubscribe".bright_purple(), address.bright_green()),
+            ProtocolRequest::PostSlate { ref from, ref to, .. } => write!(f, "{} from {} to {}", "PostSlate".bright_purple(), from.bright_green(), to.bright_green()),
+            ProtocolRequest::IsOnline { ref address } => write!(f, "{} {}", "IsOnline".bright_purple(), address.bright_green()),
         }
     }
 }
@@ -45,19 +81,67 @@ impl Display for ProtocolRequest {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ProtocolResponse {
-    Ok,
+    Ok {
+        // the relay may hand out an id for a posted slate to track its delivery by
+        #[serde(default)]
+        id: Option<String>,
+    },
     Error { kind: ProtocolError, description: String },
-    Challenge { str: String },
-    Slate { from: String, str: String, signature: String, challenge: String },
+    Challenge {
+        str: String,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        #[serde(default)]
+        public_key: Option<String>,
+        // seconds since the epoch on the relay clock
+        #[serde(default)]
+        timestamp: Option<u64>,
+    },
+    Slate {
+        from: String,
+        str: String,
+        signature: String,
+        challenge: String,
+        #[serde(default)]
+        received_at: Option<u64>,
+        #[serde(default)]
+        relay: Option<String>,
+        #[serde(default)]
+        compressed: bool,
+        // counts up per subscription, on relays that number the slates they forward
+        #[serde(default)]
+        sequence: Option<u64>,
+        #[serde(default)]
+        memo: Option<String>,
+    },
+    Binding { address: String, signature: String },
+    Delivered {
+        to: String,
+        #[serde(default)]
+        id: Option<String>,
+    },
+    Presence {
+        online: bool,
+        // the slate versions the recipient subscribed with, if the relay knows them
+        #[serde(default)]
+        slate_versions: Vec<u64>,
+    },
 }
 
 impl Display for ProtocolResponse {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match *self {
-            ProtocolResponse::Ok => write!(f, "{}", "Ok".cyan()),
+            ProtocolResponse::Ok { .. } => write!(f, "{}", "Ok".cyan()),
             ProtocolResponse::Error { ref kind, description: _ } => write!(f, "{}: {}", "ERROR".bright_red(), kind),
-            ProtocolResponse::Challenge { ref str } => write!(f, "{} {}", "Challenge".cyan(), str.bright_green()),
-            ProtocolResponse::Slate { ref from, str: _, signature: _, challenge: _ } => write!(f, "{} from {}", "Slate".cyan(), from.bright_green()),
+            ProtocolResponse::Challenge { ref str, .. } => write!(f, "{} {}", "Challenge".cyan(), str.bright_green()),
+            ProtocolResponse::Slate { ref from, .. } => write!(f, "{} from {}", "Slate".cyan(), from.bright_green()),
+            ProtocolResponse::Binding { ref address, signature: _ } => write!(f, "{} for {}", "Binding".cyan(), address.bright_green()),
+            ProtocolResponse::Delivered { ref to, .. } => write!(f, "{} to {}", "Delivered".cyan(), to.bright_green()),
+            ProtocolResponse::Presence { online, .. } => write!(f, "{} {}", "Presence".cyan(), match online { true => "online".bright_green(), false => "offline".bright_red() }),
         }
     }
-}
\ No newline at end of file
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
@@ -0,0 +1,124 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::clock::Clock;
+
+// a token bucket holding up to a second worth of posts, so short bursts still go out right away
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    // the bucket starts full at the first post, on whatever clock that one was timed with
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(posts_per_second: f64) -> Self {
+        let capacity = posts_per_second.max(1.0);
+        Self {
+            rate: posts_per_second,
+            capacity,
+            tokens: capacity,
+            last_refill: None,
+        }
+    }
+
+    // takes a token when one is available, otherwise tells how long until the next one is
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let last_refill = self.last_refill.unwrap_or(now);
+        if now > last_refill {
+            let elapsed = now - last_refill;
+            let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        }
+        self.last_refill = Some(now.max(last_refill));
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - self.tokens) / self.rate;
+        Err(Duration::from_millis((wait * 1000.0).ceil() as u64))
+    }
+}
+
+// caps how many connections posts may have open at once, the others wait for a free slot
+pub struct PostSlots {
+    capacity: usize,
+    taken: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl PostSlots {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            taken: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // the slot is given back once the returned guard is dropped. `timeout` runs on `clock`, a
+    // slot that is freed up wakes the waiter right away either way.
+    pub fn acquire(slots: &Arc<Self>, clock: &Clock, timeout: Duration) -> Option<PostSlot> {
+        let deadline = clock.now() + timeout;
+        let mut taken = slots.taken.lock().unwrap();
+        while *taken >= slots.capacity {
+            let now = clock.now();
+            if now >= deadline {
+                return None;
+            }
+            taken = slots.freed.wait_timeout(taken, deadline - now).unwrap().0;
+        }
+        *taken += 1;
+        Some(PostSlot { slots: slots.clone() })
+    }
+}
+
+pub struct PostSlot {
+    slots: Arc<PostSlots>,
+}
+
+impl Drop for PostSlot {
+    fn drop(&mut self) {
+        *self.slots.taken.lock().unwrap() -= 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::{TokenBucket, PostSlots};
+    use super::super::clock::SystemClock;
+
+    #[test]
+    fn limits_posts_per_second() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0);
+        assert!(bucket.try_take(start).is_ok());
+        assert!(bucket.try_take(start).is_ok());
+        assert_eq!(bucket.try_take(start), Err(Duration::from_millis(500)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)).is_ok());
+        assert!(bucket.try_take(start + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn limits_concurrent_posts() {
+        let slots = Arc::new(PostSlots::new(1));
+        let slot = PostSlots::acquire(&slots, &SystemClock, Duration::from_millis(10)).unwrap();
+        assert!(PostSlots::acquire(&slots, &SystemClock, Duration::from_millis(10)).is_none());
+        let waiting = {
+            let slots = slots.clone();
+            thread::spawn(move || PostSlots::acquire(&slots, &SystemClock, Duration::from_secs(5)).is_some())
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(slot);
+        assert!(waiting.join().unwrap());
+    }
+}
@@ -0,0 +1,36 @@
+use common::{Error, Wallet713Error};
+use contacts::{Address, GrinboxAddress};
+
+// turns what the user typed into the grinbox address to dial. other implementations can look
+// names up elsewhere, e.g. in a dns txt record.
+pub trait AddressResolver: Send + Sync {
+    fn resolve(&self, name: &str) -> Result<GrinboxAddress, Error>;
+}
+
+// takes the name as a grinbox address as it is
+pub struct RawAddressResolver;
+
+impl AddressResolver for RawAddressResolver {
+    fn resolve(&self, name: &str) -> Result<GrinboxAddress, Error> {
+        GrinboxAddress::from_str(name).map_err(|e| {
+            debug!("could not parse address `{}`: {}", name, e);
+            Wallet713Error::AddressResolutionFailed(name.to_string()).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use common::crypto::{generate_secret_key, public_key_from_secret_key};
+    use contacts::GrinboxAddress;
+    use super::{AddressResolver, RawAddressResolver};
+
+    #[test]
+    fn resolves_raw_addresses() {
+        let secret_key = generate_secret_key().unwrap();
+        let address = GrinboxAddress::new(public_key_from_secret_key(&secret_key).unwrap(), "127.0.0.1".to_string(), None);
+        let resolved = RawAddressResolver.resolve(&address.to_string()).unwrap();
+        assert_eq!(resolved, address);
+        assert!(RawAddressResolver.resolve("alice.example.com").is_err());
+    }
+}
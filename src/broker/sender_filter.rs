@@ -0,0 +1,48 @@
+use contacts::GrinboxAddress;
+
+// which senders slates are taken from. addresses are matched by their public key alone, so
+// the same party is recognized no matter which relay it posts through.
+#[derive(Clone, Debug)]
+pub enum SenderFilter {
+    // only these senders, or anyone when it is empty
+    Allow(Vec<GrinboxAddress>),
+    // anyone but these senders
+    Block(Vec<GrinboxAddress>),
+}
+
+impl SenderFilter {
+    pub fn accepts(&self, from: &GrinboxAddress) -> bool {
+        match *self {
+            SenderFilter::Allow(ref allowed) => allowed.is_empty() || listed(allowed, from),
+            SenderFilter::Block(ref blocked) => !listed(blocked, from),
+        }
+    }
+}
+
+fn listed(addresses: &[GrinboxAddress], from: &GrinboxAddress) -> bool {
+    addresses.iter().any(|address| address.public_key == from.public_key)
+}
+
+#[cfg(test)]
+mod test {
+    use common::crypto::{generate_secret_key, public_key_from_secret_key};
+    use contacts::GrinboxAddress;
+    use super::SenderFilter;
+
+    #[test]
+    fn filters_senders() {
+        let public_key = public_key_from_secret_key(&generate_secret_key().unwrap()).unwrap();
+        let friend = GrinboxAddress::new(public_key.clone(), "grinbox.io".to_string(), None);
+        let friend_elsewhere = GrinboxAddress::new(public_key, "relay.example.com".to_string(), Some(13420));
+        let public_key = public_key_from_secret_key(&generate_secret_key().unwrap()).unwrap();
+        let stranger = GrinboxAddress::new(public_key, "grinbox.io".to_string(), None);
+
+        assert!(SenderFilter::Allow(vec![]).accepts(&stranger));
+        let allow = SenderFilter::Allow(vec![friend.clone()]);
+        assert!(allow.accepts(&friend) && allow.accepts(&friend_elsewhere));
+        assert!(!allow.accepts(&stranger));
+        let block = SenderFilter::Block(vec![friend]);
+        assert!(!block.accepts(&friend_elsewhere));
+        assert!(block.accepts(&stranger));
+    }
+}
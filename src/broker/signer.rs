@@ -0,0 +1,33 @@
+use common::Error;
+use common::crypto::{SecretKey, Hex, sign_challenge};
+
+// signs grinbox challenges. the default signs with the in-process grinbox key, other
+// implementations can forward the challenge to e.g. a hardware device.
+pub trait ChallengeSigner {
+    fn sign(&self, challenge: &str) -> Result<String, Error>;
+}
+
+// hands out a distinct key for each grinbox address, so running many addresses does not mean
+// sharing one key between them. the wallet derives them from its seed.
+pub trait AddressKeySource {
+    fn address_key(&self, index: u32) -> Result<SecretKey, Error>;
+}
+
+pub struct KeySigner {
+    secret_key: SecretKey,
+}
+
+impl KeySigner {
+    pub fn new(secret_key: &SecretKey) -> Self {
+        Self {
+            secret_key: secret_key.clone(),
+        }
+    }
+}
+
+impl ChallengeSigner for KeySigner {
+    fn sign(&self, challenge: &str) -> Result<String, Error> {
+        let signature = sign_challenge(challenge, &self.secret_key)?;
+        Ok(signature.to_hex())
+    }
+}
@@ -0,0 +1,71 @@
+use serde_json::Value;
+
+use common::{Error, Wallet713Error};
+
+pub const MIN_SLATE_VERSION: u64 = 0;
+pub const MAX_SLATE_VERSION: u64 = 1;
+
+pub fn supported_slate_versions() -> Vec<u64> {
+    (MIN_SLATE_VERSION..MAX_SLATE_VERSION + 1).collect()
+}
+
+pub fn slate_version(slate: &Value) -> u64 {
+    slate.get("version").and_then(|v| v.as_u64()).unwrap_or(0)
+}
+
+pub fn check_slate_version(slate: &Value) -> Result<u64, Error> {
+    let version = slate_version(slate);
+    let supported = supported_slate_versions();
+    if !supported.contains(&version) {
+        let supported: Vec<String> = supported.iter().map(|v| v.to_string()).collect();
+        Err(Wallet713Error::SlateVersionMismatch(version, supported.join(", ")))?;
+    }
+    Ok(version)
+}
+
+// the only difference between the versions we know of is the presence of the version field
+// itself, which makes converting between them lossless both ways
+pub fn convert_slate(mut slate: Value, target: u64) -> Result<Value, Error> {
+    let version = slate_version(&slate);
+    if version < MIN_SLATE_VERSION || version > MAX_SLATE_VERSION {
+        Err(Wallet713Error::UnsupportedSlateVersion(version))?;
+    }
+    if target < MIN_SLATE_VERSION || target > MAX_SLATE_VERSION {
+        Err(Wallet713Error::UnsupportedSlateVersion(target))?;
+    }
+    if version == target {
+        return Ok(slate);
+    }
+    match slate.as_object_mut() {
+        Some(fields) => match target {
+            0 => { fields.remove("version"); },
+            _ => { fields.insert("version".to_string(), Value::from(target)); },
+        },
+        None => Err(Wallet713Error::UnsupportedSlateVersion(version))?,
+    };
+    Ok(slate)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{convert_slate, slate_version, check_slate_version, supported_slate_versions};
+
+    #[test]
+    fn can_convert_between_slate_versions() {
+        let slate = json!({ "id": "test" });
+        let upgraded = convert_slate(slate, 1).unwrap();
+        assert_eq!(slate_version(&upgraded), 1);
+        let downgraded = convert_slate(upgraded, 0).unwrap();
+        assert_eq!(slate_version(&downgraded), 0);
+        assert!(downgraded.get("version").is_none());
+        assert!(convert_slate(json!({ "version": 7 }), 1).is_err());
+    }
+
+    #[test]
+    fn can_check_slate_versions() {
+        assert_eq!(supported_slate_versions(), vec![0, 1]);
+        assert_eq!(check_slate_version(&json!({ "id": "test" })).unwrap(), 0);
+        assert_eq!(check_slate_version(&json!({ "version": 1 })).unwrap(), 1);
+        assert!(check_slate_version(&json!({ "version": 7 })).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use serde_json;
+
+use grin_core::libtx::slate::Slate;
+
+use common::Error;
+
+use super::correlation::SavedCorrelation;
+use super::types::DeliveryGuarantee;
+
+// a post that failed and waits for `retry_last_post`, with everything needed to send it again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPost {
+    pub slate: Slate,
+    pub recipient: String,
+    // the display form of the recipient leaves out the default port, even one given explicitly
+    #[serde(default)]
+    pub port: Option<u16>,
+    pub memo: Option<String>,
+    pub guarantee: DeliveryGuarantee,
+    pub enqueued_at: SystemTime,
+    pub attempts: u32,
+}
+
+// what a restarted process needs to carry on where the previous one stopped. there are no keys
+// in here, the new process brings its own.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BrokerState {
+    pub failed_post: Option<SavedPost>,
+    pub correlations: Vec<SavedCorrelation>,
+}
+
+impl BrokerState {
+    // goes through a temporary file, so a crash halfway leaves the previous state intact
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let temporary = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temporary)?;
+            file.write_all(serde_json::to_string(self)?.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut file = File::open(path)?;
+        let mut json = String::new();
+        file.read_to_string(&mut json)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::time::SystemTime;
+    use grin_core::libtx::slate::Slate;
+
+    use super::{BrokerState, SavedPost};
+    use super::super::correlation::{SavedCorrelation, SlateDirection, SlateState};
+    use super::super::types::DeliveryGuarantee;
+
+    #[test]
+    fn saves_and_loads() {
+        fs::create_dir_all("./target/tests").unwrap();
+        let path = Path::new("./target/tests/broker-state.json");
+        let slate = Slate::blank(2);
+        let state = BrokerState {
+            failed_post: Some(SavedPost {
+                slate: slate.clone(),
+                recipient: "xd7sCQ9bQuQXp4yCn8GSELcuSxnpcPrPoEWJzvPBc5vxyXPQz6PJ@grinbox.io".to_string(),
+                port: Some(443),
+                memo: Some("invoice 42".to_string()),
+                guarantee: DeliveryGuarantee::Delivered,
+                enqueued_at: SystemTime::now(),
+                attempts: 2,
+            }),
+            correlations: vec![SavedCorrelation {
+                slate_id: slate.id.to_string(),
+                direction: SlateDirection::Outgoing,
+                peer: "alice".to_string(),
+                state: SlateState::Sent,
+                age_ms: 1000,
+            }],
+        };
+        state.save(path).unwrap();
+        let loaded = BrokerState::load(path).unwrap();
+        let post = loaded.failed_post.unwrap();
+        assert_eq!(post.slate.id, slate.id);
+        assert_eq!(post.memo, Some("invoice 42".to_string()));
+        assert_eq!(post.guarantee, DeliveryGuarantee::Delivered);
+        assert_eq!(post.attempts, 2);
+        assert_eq!(post.port, Some(443));
+        assert_eq!(loaded.correlations.len(), 1);
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
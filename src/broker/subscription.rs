@@ -0,0 +1,167 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use grin_core::libtx::slate::Slate;
+
+use contacts::Address;
+
+use super::types::{Subscriber, SubscriptionHandler, CloseReason, BrokerStatus, SlateMeta};
+
+#[derive(Default)]
+struct Closed {
+    reason: Mutex<Option<CloseReason>>,
+    signal: Condvar,
+}
+
+impl Closed {
+    fn wait(&self, deadline: Option<Instant>) -> Option<CloseReason> {
+        let mut reason = self.reason.lock().unwrap();
+        while reason.is_none() {
+            reason = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.signal.wait_timeout(reason, deadline - now).unwrap().0
+                },
+                None => self.signal.wait(reason).unwrap(),
+            };
+        }
+        reason.clone()
+    }
+}
+
+// a running subscription, as `Subscriber::start` hands it back. it keeps the subscriber it came
+// from alive, so the subscription does not end just because the subscriber went out of scope.
+pub struct Subscription {
+    subscriber: Box<Subscriber + Send>,
+    closed: Vec<Arc<Closed>>,
+}
+
+impl Subscription {
+    // wraps `handler` so the subscription learns when it closed. the subscriber has to be started
+    // with the returned handler.
+    pub fn watch(subscriber: Box<Subscriber + Send>, handler: Box<SubscriptionHandler + Send>) -> (Self, Box<SubscriptionHandler + Send>) {
+        let closed = Arc::new(Closed::default());
+        let handler = Box::new(WatchedHandler { inner: handler, closed: closed.clone() });
+        (Self { subscriber, closed: vec![closed] }, handler)
+    }
+
+    // one subscription over several others, it has closed once all of them have
+    #[cfg(test)]
+    pub fn combine(subscriber: Box<Subscriber + Send>, parts: Vec<Subscription>) -> Self {
+        let closed = parts.into_iter().flat_map(|part| part.closed).collect();
+        Self { subscriber, closed }
+    }
+
+    #[cfg(test)]
+    pub fn stop(&self) {
+        self.subscriber.stop();
+    }
+
+    #[cfg(test)]
+    pub fn is_running(&self) -> bool {
+        self.subscriber.is_running()
+    }
+
+    pub fn status(&self) -> BrokerStatus {
+        self.subscriber.status()
+    }
+
+    // blocks until the subscription closed and returns why, or `None` once `timeout` passed.
+    // when it is made of several, the first reason that is not `Normal` is returned.
+    pub fn wait(&self, timeout: Option<Duration>) -> Option<CloseReason> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut result = None;
+        for closed in &self.closed {
+            let reason = closed.wait(deadline)?;
+            result = match (result, reason) {
+                (Some(CloseReason::Normal), reason) | (None, reason) => Some(reason),
+                (result, _) => result,
+            };
+        }
+        result
+    }
+}
+
+struct WatchedHandler {
+    inner: Box<SubscriptionHandler + Send>,
+    closed: Arc<Closed>,
+}
+
+impl SubscriptionHandler for WatchedHandler {
+    fn on_open(&self) {
+        self.inner.on_open();
+    }
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        self.inner.on_slate(from, slate);
+    }
+
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, meta: &SlateMeta) {
+        self.inner.on_slate_with_meta(from, slate, meta);
+    }
+
+    // the handler hears about it first, so it is done by the time `wait` returns
+    fn on_close(&self, result: CloseReason) {
+        self.inner.on_close(result.clone());
+        *self.closed.reason.lock().unwrap() = Some(result);
+        self.closed.signal.notify_all();
+    }
+
+    fn on_dropped(&self) {
+        self.inner.on_dropped();
+    }
+
+    fn on_reestablished(&self) {
+        self.inner.on_reestablished();
+    }
+
+    fn on_reconnecting(&self, relay: &str) {
+        self.inner.on_reconnecting(relay);
+    }
+
+    fn on_slate_version_unsupported(&self, from: &Address, version: u64) {
+        self.inner.on_slate_version_unsupported(from, version);
+    }
+
+    fn on_capability_downgrade(&self, lost: Vec<String>) {
+        self.inner.on_capability_downgrade(lost);
+    }
+
+    fn on_channel_rebound(&self) {
+        self.inner.on_channel_rebound();
+    }
+
+    fn on_unverified_slate(&self, from: &str, raw: &str) {
+        self.inner.on_unverified_slate(from, raw);
+    }
+
+    fn ordered_per_sender(&self) -> bool {
+        self.inner.ordered_per_sender()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::super::mock::{MockSubscriber, NoopHandler};
+    use super::super::types::{Subscriber, CloseReason};
+
+    #[test]
+    fn waits_for_the_subscription_to_close() {
+        let mut subscriber = MockSubscriber::new();
+        let subscription = subscriber.start(Box::new(NoopHandler)).unwrap();
+        assert!(subscription.is_running());
+        assert!(subscription.wait(Some(Duration::from_millis(10))).is_none());
+
+        subscription.stop();
+        assert!(!subscriber.is_running());
+        match subscription.wait(None) {
+            Some(CloseReason::Normal) => {},
+            _ => panic!("the subscription did not close normally"),
+        }
+    }
+}
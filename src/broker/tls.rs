@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex};
+use mio::tcp::TcpStream;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslOptions, SslStream};
+use url::Url;
+use ws::{Handler, Handshake, Message, Result as WsResult, ErrorKind as WsErrorKind, Error as WsError};
+
+use common::{Error, Wallet713Error};
+use common::crypto::to_hex;
+use super::config::TlsVersion;
+
+enum TlsFailure {
+    CertificatePinMismatch(String),
+    Handshake(String, String),
+}
+
+// performs the tls handshake for grinbox connections. ws only reports handshake failures to
+// `on_error`, so the reason is kept around for the caller to pick up once `connect` returns.
+#[derive(Clone)]
+pub struct TlsConnector {
+    pinned_certificate: Option<String>,
+    ca_bundle: Option<String>,
+    min_version: TlsVersion,
+    failure: Arc<Mutex<Option<TlsFailure>>>,
+}
+
+impl TlsConnector {
+    // without a ca bundle the system root store is used
+    pub fn new(pinned_certificate: Option<String>, ca_bundle: Option<String>, min_version: TlsVersion) -> Self {
+        Self {
+            pinned_certificate: pinned_certificate.map(|pin| pin.replace(":", "").to_lowercase()),
+            ca_bundle,
+            min_version,
+            failure: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn connect(&self, sock: TcpStream, url: &Url) -> WsResult<SslStream<TcpStream>> {
+        let domain = url.domain().ok_or_else(|| {
+            WsError::new(WsErrorKind::Protocol, format!("could not parse domain from {}!", url))
+        })?;
+        let mut builder = SslConnector::builder(SslMethod::tls())
+            .map_err(|e| WsError::new(WsErrorKind::Internal, format!("could not set up tls: {}", e)))?;
+        // switching the old versions off works with every openssl, unlike setting a minimum
+        let mut options = SslOptions::NO_SSLV2 | SslOptions::NO_SSLV3 | SslOptions::NO_TLSV1 | SslOptions::NO_TLSV1_1;
+        if self.min_version == TlsVersion::Tls13 {
+            options |= SslOptions::NO_TLSV1_2;
+        }
+        builder.set_options(options);
+        if let Some(ref ca_bundle) = self.ca_bundle {
+            builder.set_ca_file(ca_bundle).map_err(|e| {
+                self.fail(TlsFailure::Handshake(url.to_string(), format!("could not load ca bundle `{}`: {}", ca_bundle, e)))
+            })?;
+        }
+        let stream = builder.build().connect(domain, sock).map_err(|e| {
+            self.fail(TlsFailure::Handshake(url.to_string(), e.to_string()))
+        })?;
+
+        if !allowed_version(stream.ssl().version_str(), self.min_version) {
+            return Err(self.fail(TlsFailure::Handshake(url.to_string(), format!("relay negotiated {}", stream.ssl().version_str()))));
+        }
+        if let Some(ref pin) = self.pinned_certificate {
+            let fingerprint = stream.ssl().peer_certificate()
+                .and_then(|cert| cert.digest(MessageDigest::sha256()).ok())
+                .map(|digest| to_hex(digest.to_vec()));
+            if fingerprint.as_ref() != Some(pin) {
+                return Err(self.fail(TlsFailure::CertificatePinMismatch(url.to_string())));
+            }
+        }
+        Ok(stream)
+    }
+
+    pub fn take_error(&self) -> Option<Error> {
+        self.failure.lock().unwrap().take().map(|failure| match failure {
+            TlsFailure::CertificatePinMismatch(url) => Wallet713Error::CertificatePinMismatch(url).into(),
+            TlsFailure::Handshake(url, reason) => Wallet713Error::TlsHandshake(url, reason).into(),
+        })
+    }
+
+    fn fail(&self, failure: TlsFailure) -> WsError {
+        let error = match failure {
+            TlsFailure::CertificatePinMismatch(_) => WsError::new(WsErrorKind::Protocol, "relay certificate does not match the pinned fingerprint!"),
+            TlsFailure::Handshake(_, ref reason) => WsError::new(WsErrorKind::Internal, format!("tls handshake failed: {}", reason)),
+        };
+        *self.failure.lock().unwrap() = Some(failure);
+        error
+    }
+}
+
+fn allowed_version(version: &str, min_version: TlsVersion) -> bool {
+    match version {
+        "TLSv1.3" => true,
+        "TLSv1.2" => min_version == TlsVersion::Tls12,
+        _ => false,
+    }
+}
+
+// lets closure based handlers go through our tls setup and report when the connection opened
+pub struct TlsHandler<F> {
+    pub tls: TlsConnector,
+    pub opened: Arc<Mutex<bool>>,
+    pub handler: F,
+}
+
+impl<F> Handler for TlsHandler<F> where F: Fn(Message) -> WsResult<()> {
+    fn on_open(&mut self, _shake: Handshake) -> WsResult<()> {
+        *self.opened.lock().unwrap() = true;
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> WsResult<()> {
+        (self.handler)(msg)
+    }
+
+    fn upgrade_ssl_client(&mut self, sock: TcpStream, url: &Url) -> WsResult<SslStream<TcpStream>> {
+        self.tls.connect(sock, url)
+    }
+}
@@ -1,27 +1,157 @@
+use std::time::SystemTime;
 use grin_core::libtx::slate::Slate;
 
-use common::Error;
+use common::{Error, Wallet713Error};
 use contacts::Address;
 
+use super::subscription::Subscription;
+
+#[derive(Clone, Debug, Default)]
+pub struct SlateMeta {
+    pub received_at: Option<u64>,
+    pub relay: Option<String>,
+    pub recipient: Option<String>,
+    // the answer to a slate we sent out before
+    pub response: bool,
+    // a note the sender attached in plain text, covered by its signature
+    pub memo: Option<String>,
+}
+
+// a snapshot of a subscription for monitoring
+#[derive(Clone, Debug, Default)]
+pub struct BrokerStatus {
+    pub connected_since: Option<SystemTime>,
+    pub reconnects: u32,
+    pub last_slate_at: Option<SystemTime>,
+    // slates waiting for the handler
+    pub pending_slates: usize,
+    pub peak_pending_slates: usize,
+    pub queue_capacity: usize,
+    // times a slate had to wait because the queue was full
+    pub backpressure_waits: u64,
+    pub dropped_slates: u64,
+}
+
+// where a subscription stands, as far as we can tell from our side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    // dialing the relay, or waiting to dial it again after the connection dropped
+    Connecting,
+    Connected,
+    Stopped,
+}
+
 pub enum CloseReason {
     Normal,
-    Abnormal(Error)
+    Abnormal(Error),
+    // the relay answered with an error that leaves the connection useless, e.g. a ban
+    ProtocolError(String),
+}
+
+impl CloseReason {
+    // whatever did not end normally as an error, for handlers that pass it on
+    pub fn into_result(self) -> Result<(), Error> {
+        match self {
+            CloseReason::Normal => Ok(()),
+            CloseReason::Abnormal(e) => Err(e),
+            CloseReason::ProtocolError(description) => Err(Wallet713Error::RelayClosed(description).into()),
+        }
+    }
+}
+
+// `failure::Error` can not be cloned, so a copy of an abnormal close only keeps the message
+impl Clone for CloseReason {
+    fn clone(&self) -> Self {
+        match *self {
+            CloseReason::Normal => CloseReason::Normal,
+            CloseReason::Abnormal(ref e) => CloseReason::Abnormal(format_err!("{}", e)),
+            CloseReason::ProtocolError(ref description) => CloseReason::ProtocolError(description.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeliveryGuarantee {
+    FireAndForget,
+    Acknowledged,
+    Delivered,
+}
+
+// what a single post sent out, so it can be proven later whether a slate left encrypted
+#[derive(Clone, Debug)]
+pub struct PostRecord {
+    pub slate_id: String,
+    pub recipient: String,
+    pub encrypted: bool,
+    // handed to our own subscriber without leaving the process
+    pub loopback: bool,
+    pub guarantee: DeliveryGuarantee,
+}
+
+// a post that is still going out, or waiting to be retried after it failed
+#[derive(Clone, Debug)]
+pub struct PendingPost {
+    pub slate_id: String,
+    pub recipient: String,
+    pub enqueued_at: SystemTime,
+    pub attempts: u32,
+}
+
+pub trait PostAuditor: Send + Sync {
+    fn on_posted(&self, record: &PostRecord);
+}
+
+// applied to every incoming slate before the handler gets it. an error drops the slate.
+pub trait SlateTransform: Send + Sync {
+    fn transform(&self, from: &Address, slate: Slate) -> Result<Slate, Error>;
+}
+
+impl<F> SlateTransform for F where F: Fn(&Address, Slate) -> Result<Slate, Error> + Send + Sync {
+    fn transform(&self, from: &Address, slate: Slate) -> Result<Slate, Error> {
+        self(from, slate)
+    }
 }
 
+// posting returns the id the relay assigned to the slate, when it told us one
 pub trait Publisher {
-    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<(), Error>;
+    fn post_slate(&self, slate: &Slate, to: &Address) -> Result<Option<String>, Error>;
+    fn post_slate_with_guarantee(&self, slate: &Slate, to: &Address, guarantee: DeliveryGuarantee) -> Result<Option<String>, Error> {
+        match guarantee {
+            DeliveryGuarantee::FireAndForget => self.post_slate(slate, to),
+            _ => Err(Wallet713Error::DeliveryGuaranteeUnsupported.into()),
+        }
+    }
 }
 
 pub trait Subscriber {
-    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<(), Error>;
+    fn start(&mut self, handler: Box<SubscriptionHandler + Send>) -> Result<Subscription, Error>;
     fn stop(&self);
     fn is_running(&self) -> bool;
+    fn status(&self) -> BrokerStatus {
+        BrokerStatus::default()
+    }
 }
 
 pub trait SubscriptionHandler: Send {
     fn on_open(&self);
     fn on_slate(&self, from: &Address, slate: &mut Slate);
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, _meta: &SlateMeta) {
+        self.on_slate(from, slate);
+    }
     fn on_close(&self, result: CloseReason);
     fn on_dropped(&self);
     fn on_reestablished(&self);
+    // the subscriber is about to switch to another relay
+    fn on_reconnecting(&self, _relay: &str) {}
+    fn on_slate_version_unsupported(&self, _from: &Address, _version: u64) {}
+    fn on_capability_downgrade(&self, _lost: Vec<String>) {}
+    fn on_channel_rebound(&self) {}
+    // only called when `report_unverified_slates` is enabled. the signature of these slates did
+    // not check out, so `from` may be forged and `raw` must never be acted upon.
+    fn on_unverified_slate(&self, _from: &str, _raw: &str) {}
+    // when slates are handled concurrently, those from the same sender still go one after the
+    // other in the order they arrived
+    fn ordered_per_sender(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,109 @@
+use grin_core::core::KernelFeatures;
+use grin_core::libtx::slate::Slate;
+
+use common::{Error, Wallet713Error};
+
+// cheap structural checks for slates that passed signature verification. they do not ask the
+// node anything, so they can only catch slates that could never make a valid transaction.
+pub fn validate_slate(slate: &Slate) -> Result<(), Error> {
+    if slate.amount == 0 {
+        Err(Wallet713Error::InvalidSlate("amount is zero".to_string()))?;
+    }
+    if slate.num_participants < 2 || slate.participant_data.len() > slate.num_participants {
+        Err(Wallet713Error::InvalidSlate(format!("{} participants for {} slots", slate.participant_data.len(), slate.num_participants)))?;
+    }
+    if slate.amount.checked_add(slate.fee).is_none() {
+        Err(Wallet713Error::InvalidSlate("amount and fee overflow".to_string()))?;
+    }
+
+    let kernels = slate.tx.kernels();
+    if kernels.is_empty() {
+        Err(Wallet713Error::InvalidSlate("transaction has no kernel".to_string()))?;
+    }
+    if kernels.iter().any(|kernel| kernel.features.contains(KernelFeatures::COINBASE)) {
+        Err(Wallet713Error::InvalidSlate("transaction has a coinbase kernel".to_string()))?;
+    }
+    let kernel_fee = kernels.iter().fold(Some(0u64), |sum, kernel| sum.and_then(|sum| sum.checked_add(kernel.fee)));
+    if kernel_fee != Some(slate.fee) {
+        Err(Wallet713Error::InvalidSlate(format!("kernel fees do not add up to the fee of {}", slate.fee)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use grin_core::core::{KernelFeatures, TxKernel};
+    use grin_core::libtx::slate::Slate;
+
+    use common::Wallet713Error;
+    use super::validate_slate;
+
+    // a slate that passes every check, for the tests to break one rule at a time
+    fn valid_slate() -> Slate {
+        let mut slate = Slate::blank(2);
+        slate.amount = 1_000_000_000;
+        slate.fee = 8_000_000;
+        let mut kernel = TxKernel::empty();
+        kernel.fee = slate.fee;
+        slate.tx = slate.tx.clone().with_kernel(kernel);
+        slate
+    }
+
+    fn rejection(slate: &Slate) -> String {
+        let error = validate_slate(slate).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::InvalidSlate(ref reason)) => reason.clone(),
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn accepts_well_formed_slates() {
+        assert!(validate_slate(&valid_slate()).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_zero_amount() {
+        let mut slate = valid_slate();
+        slate.amount = 0;
+        assert_eq!(rejection(&slate), "amount is zero");
+    }
+
+    #[test]
+    fn rejects_too_few_participants() {
+        let mut slate = valid_slate();
+        slate.num_participants = 1;
+        assert_eq!(rejection(&slate), "0 participants for 1 slots");
+    }
+
+    #[test]
+    fn rejects_an_overflowing_fee() {
+        let mut slate = valid_slate();
+        slate.amount = u64::max_value();
+        assert_eq!(rejection(&slate), "amount and fee overflow");
+    }
+
+    #[test]
+    fn rejects_a_transaction_without_kernel() {
+        let mut slate = valid_slate();
+        slate.tx = Slate::blank(2).tx;
+        assert_eq!(rejection(&slate), "transaction has no kernel");
+    }
+
+    #[test]
+    fn rejects_a_coinbase_kernel() {
+        let mut slate = valid_slate();
+        let mut kernel = TxKernel::empty();
+        kernel.features = KernelFeatures::COINBASE;
+        kernel.fee = slate.fee;
+        slate.tx = Slate::blank(2).tx.with_kernel(kernel);
+        assert_eq!(rejection(&slate), "transaction has a coinbase kernel");
+    }
+
+    #[test]
+    fn rejects_kernel_fees_that_do_not_add_up() {
+        let mut slate = valid_slate();
+        slate.fee += 1;
+        assert_eq!(rejection(&slate), format!("kernel fees do not add up to the fee of {}", slate.fee));
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use openssl::ssl::{SslConnector, SslMethod};
+use serde_json::{self, Value};
+use url::Url;
+
+use grin_core::libtx::slate::Slate;
+
+use common::{Error, Wallet713Error};
+use contacts::Address;
+
+use super::clock::{Clock, SystemClock};
+use super::types::{SubscriptionHandler, CloseReason, SlateMeta};
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+const REQUEST_TIMEOUT_MS: u64 = 10000;
+const QUEUE_SIZE: usize = 256;
+
+// posts every slate, and every change of the subscription, as json to an http endpoint. the
+// posting happens on a thread of its own, so a slow endpoint never holds up the subscriber.
+// each event is retried a few times before it is given up on and logged.
+pub struct WebhookHandler {
+    endpoint: Endpoint,
+    queue: Mutex<Option<SyncSender<Value>>>,
+}
+
+#[derive(Clone)]
+struct Endpoint {
+    url: Url,
+    retries: u32,
+    retry_delay: Duration,
+    clock: Arc<Clock>,
+}
+
+impl WebhookHandler {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let url = Url::parse(url)?;
+        match url.scheme() {
+            "http" | "https" => {},
+            scheme => Err(Wallet713Error::WebhookFailed(format!("unsupported scheme `{}`", scheme)))?,
+        };
+        Ok(Self {
+            endpoint: Endpoint {
+                url,
+                retries: DEFAULT_RETRIES,
+                retry_delay: Duration::from_millis(DEFAULT_RETRY_DELAY_MS),
+                clock: Arc::new(SystemClock),
+            },
+            queue: Mutex::new(None),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_retries(mut self, retries: u32, retry_delay: Duration) -> Self {
+        self.endpoint.retries = retries;
+        self.endpoint.retry_delay = retry_delay;
+        self
+    }
+
+    // waits between retries on the clock of the broker, see `GrinboxSubscriber::clock`
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.endpoint.clock = clock;
+        self
+    }
+
+    // events are posted in the order they came in. once the endpoint fell too far behind, new
+    // ones are dropped rather than piling up.
+    fn notify(&self, event: Value) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.is_none() {
+            *queue = Some(self.endpoint.start());
+        }
+        match queue.as_ref().unwrap().try_send(event) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => error!("webhook {} is too far behind, dropping an event", self.endpoint.url),
+            Err(TrySendError::Disconnected(_)) => error!("webhook thread is gone, dropping an event"),
+        }
+    }
+}
+
+impl Endpoint {
+    // the thread ends once the handler is dropped and everything queued was posted
+    fn start(&self) -> SyncSender<Value> {
+        let (sender, receiver) = sync_channel::<Value>(QUEUE_SIZE);
+        let endpoint = self.clone();
+        thread::spawn(move || {
+            for event in receiver {
+                if let Err(e) = endpoint.post(&event) {
+                    error!("{}", e);
+                }
+            }
+        });
+        sender
+    }
+
+    fn post(&self, event: &Value) -> Result<(), Error> {
+        let body = serde_json::to_string(event)?;
+        let mut attempt = 0;
+        loop {
+            match self.post_once(&body) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if attempt >= self.retries {
+                        Err(Wallet713Error::WebhookFailed(e.to_string()))?;
+                    }
+                    debug!("webhook attempt {} failed: {}", attempt + 1, e);
+                },
+            };
+            attempt += 1;
+            self.clock.sleep(self.retry_delay);
+        }
+    }
+
+    fn post_once(&self, body: &str) -> Result<(), Error> {
+        let host = self.url.host_str().ok_or(Wallet713Error::WebhookFailed(format!("`{}` has no host", self.url)))?;
+        let port = self.url.port_or_known_default().unwrap_or(80);
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))?;
+        stream.set_write_timeout(Some(Duration::from_millis(REQUEST_TIMEOUT_MS)))?;
+
+        let mut path = self.url.path().to_string();
+        if let Some(query) = self.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        // the port is part of the host header unless it is the default of the scheme
+        let host_header = match self.url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path, host_header, body.len(), body);
+
+        let response = match self.url.scheme() {
+            "https" => {
+                let connector = SslConnector::builder(SslMethod::tls())?.build();
+                let mut stream = connector.connect(host, stream)
+                    .map_err(|e| Wallet713Error::WebhookFailed(e.to_string()))?;
+                exchange(&mut stream, &request)?
+            },
+            _ => {
+                let mut stream = stream;
+                exchange(&mut stream, &request)?
+            },
+        };
+
+        let status_line = response.lines().next().unwrap_or("").to_string();
+        let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok()).unwrap_or(0);
+        if status < 200 || status >= 300 {
+            Err(Wallet713Error::WebhookFailed(format!("endpoint answered `{}`", status_line)))?;
+        }
+        Ok(())
+    }
+}
+
+// only the status line of the answer matters, so there is no need to read all of it
+fn exchange<S: Read + Write>(stream: &mut S, request: &str) -> Result<String, Error> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let mut buf = [0u8; 1024];
+    let read = stream.read(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf[..read]).into_owned())
+}
+
+impl SubscriptionHandler for WebhookHandler {
+    fn on_open(&self) {
+        self.notify(json!({ "event": "open" }));
+    }
+
+    fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        self.on_slate_with_meta(from, slate, &SlateMeta::default());
+    }
+
+    fn on_slate_with_meta(&self, from: &Address, slate: &mut Slate, meta: &SlateMeta) {
+        let slate = match serde_json::to_value(&*slate) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("could not serialize slate from {} for the webhook: {}", from.stripped(), e);
+                return;
+            },
+        };
+        self.notify(json!({
+            "event": "slate",
+            "from": from.stripped(),
+            "slate": slate,
+            "meta": {
+                "received_at": meta.received_at,
+                "relay": meta.relay,
+                "recipient": meta.recipient,
+                "response": meta.response,
+                "memo": meta.memo,
+            },
+        }));
+    }
+
+    fn on_close(&self, reason: CloseReason) {
+        let reason = match reason {
+            CloseReason::Normal => Value::Null,
+            CloseReason::Abnormal(e) => Value::from(e.to_string()),
+            CloseReason::ProtocolError(description) => Value::from(description),
+        };
+        self.notify(json!({ "event": "close", "reason": reason }));
+    }
+
+    fn on_dropped(&self) {
+        self.notify(json!({ "event": "dropped" }));
+    }
+
+    fn on_reestablished(&self) {
+        self.notify(json!({ "event": "reestablished" }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use contacts::{Address, GrinboxAddress};
+    use super::super::clock::{Clock, MockClock};
+    use super::super::types::{SubscriptionHandler, SlateMeta};
+    use super::WebhookHandler;
+
+    // answers the first `failures` requests with an error and every other one with success
+    fn endpoint(requests: usize, failures: usize) -> (String, ::std::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/slates", listener.local_addr().unwrap());
+        let (received, requests_received) = channel();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().take(requests).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 8192];
+                let read = stream.read(&mut buf).unwrap();
+                received.send(String::from_utf8_lossy(&buf[..read]).into_owned()).unwrap();
+                let status = match i < failures {
+                    true => "500 Internal Server Error",
+                    false => "200 OK",
+                };
+                stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+            }
+        });
+        (url, requests_received)
+    }
+
+    #[test]
+    fn retries_until_delivered() {
+        let (url, requests) = endpoint(2, 1);
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let handler = WebhookHandler::new(&url).unwrap().with_retries(1, Duration::from_secs(3600)).with_clock(clock.clone());
+        assert!(handler.endpoint.post(&json!({ "event": "open" })).is_ok());
+        for _ in 0..2 {
+            let request = requests.recv().unwrap();
+            assert!(request.starts_with("POST /slates HTTP/1.1\r\n"));
+            // the endpoint listens on a port of its own, which has to be in the host header
+            assert!(request.contains(&format!("\r\nHost: {}\r\n", url.trim_start_matches("http://").trim_end_matches("/slates"))));
+            assert!(request.ends_with("{\"event\":\"open\"}"));
+        }
+        // the retry waited on the clock it was given
+        assert_eq!(clock.now() - start, Duration::from_secs(3600));
+        assert!(WebhookHandler::new("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn posts_without_holding_up_the_subscriber() {
+        let (url, requests) = endpoint(2, 0);
+        let handler = WebhookHandler::new(&url).unwrap().with_retries(3, Duration::from_secs(3600));
+        let start = Instant::now();
+        handler.on_open();
+        handler.on_dropped();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(requests.recv_timeout(Duration::from_secs(5)).unwrap().ends_with("{\"event\":\"open\"}"));
+        assert!(requests.recv_timeout(Duration::from_secs(5)).unwrap().ends_with("{\"event\":\"dropped\"}"));
+    }
+
+    #[test]
+    fn includes_the_slate_meta() {
+        let (url, requests) = endpoint(1, 0);
+        let handler = WebhookHandler::new(&url).unwrap();
+        let from = GrinboxAddress::from_str("xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU").unwrap();
+        let mut slate = ::grin_core::libtx::slate::Slate::blank(2);
+        let meta = SlateMeta {
+            relay: Some("grinbox.io:443".to_string()),
+            recipient: Some("grinbox://recipient".to_string()),
+            memo: Some("invoice 42".to_string()),
+            ..SlateMeta::default()
+        };
+        handler.on_slate_with_meta(&from, &mut slate, &meta);
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        let event: ::serde_json::Value = ::serde_json::from_str(body).unwrap();
+        assert_eq!(event["event"], "slate");
+        assert_eq!(event["from"], from.stripped());
+        assert_eq!(event["meta"]["relay"], "grinbox.io:443");
+        assert_eq!(event["meta"]["recipient"], "grinbox://recipient");
+        assert_eq!(event["meta"]["memo"], "invoice 42");
+    }
+}
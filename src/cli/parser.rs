@@ -170,6 +170,9 @@ impl<'a, 'b> Parser {
                     .arg(
                         Arg::from_usage("[message] -g, --message=<message> 'the message to include in the tx'")
                     )
+                    .arg(
+                        Arg::from_usage("[memo] -m, --memo=<memo> 'a note for the grinbox recipient, sent along in plain text'")
+                    )
             )
             .subcommand(
                 SubCommand::with_name("invoice")
@@ -226,5 +229,45 @@ impl<'a, 'b> Parser {
                 SubCommand::with_name("check")
                     .about("checks a wallet's outputs against a live node, repairing and restoring missing outputs if required")
             )
+            .subcommand(
+                SubCommand::with_name("grinbox")
+                    .about("grinbox relay utilities")
+                    .subcommand(
+                        SubCommand::with_name("ping")
+                            .about("measures the round trip time to a grinbox relay")
+                            .arg(
+                                Arg::from_usage("[address] -a, --address=<address> 'the grinbox address whose relay to ping, defaults to your own'")
+                            )
+                    )
+                    .subcommand(
+                        SubCommand::with_name("online")
+                            .about("asks the relay whether a grinbox address is listening")
+                            .arg(
+                                Arg::from_usage("<address> 'the grinbox address or @contact to ask about'")
+                            )
+                    )
+                    .subcommand(
+                        SubCommand::with_name("status")
+                            .about("shows the state of the grinbox listener")
+                    )
+                    .subcommand(
+                        SubCommand::with_name("pending")
+                            .about("lists the slates still going out over grinbox")
+                    )
+                    .subcommand(
+                        SubCommand::with_name("retry")
+                            .about("sends the last slate that failed to post over grinbox again")
+                    )
+                    .subcommand(
+                        SubCommand::with_name("receive")
+                            .about("waits for a single slate on your grinbox address and stores it in a file")
+                            .arg(
+                                Arg::from_usage("-f, --file=<file> 'the file to store the slate in'")
+                            )
+                            .arg(
+                                Arg::from_usage("[timeout] -t, --timeout=<seconds> 'how long to wait for the slate, defaults to 60 seconds'")
+                            )
+                    )
+            )
     }
 }
\ No newline at end of file
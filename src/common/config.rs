@@ -9,7 +9,7 @@ use grin_core::global::{ChainTypes, is_mainnet};
 
 use crate::common::error::Wallet713Error;
 use contacts::{GrinboxAddress, DEFAULT_GRINBOX_PORT};
-use super::crypto::{SecretKey, PublicKey, public_key_from_secret_key};
+use super::crypto::SecretKey;
 
 const WALLET713_HOME: &str = ".wallet713";
 const WALLET713_DEFAULT_CONFIG_FILENAME: &str = "wallet713.toml";
@@ -34,6 +34,13 @@ pub struct Wallet713Config {
     pub keybase_listener_auto_start: Option<bool>,
     pub max_auto_accept_invoice: Option<u64>,
     pub default_keybase_ttl: Option<String>,
+    pub grinbox_pinned_certificate: Option<String>,
+    pub grinbox_ca_bundle: Option<String>,
+    pub grinbox_fallback_relays: Option<Vec<String>>,
+    pub pretty_slates: Option<bool>,
+    pub grinbox_webhook_url: Option<String>,
+    pub grinbox_allowed_senders: Option<Vec<String>>,
+    pub grinbox_blocked_senders: Option<Vec<String>>,
     #[serde(skip)]
     config_home: Option<String>,
     #[serde(skip)]
@@ -112,17 +119,16 @@ impl Wallet713Config {
         self.grinbox_e2e_encryption.unwrap_or(is_mainnet())
     }
 
+    pub fn pretty_slates(&self) -> bool {
+        self.pretty_slates.unwrap_or(false)
+    }
+
     pub fn grinbox_address_index(&self) -> u32 {
         self.grinbox_address_index.unwrap_or(0)
     }
 
     pub fn get_grinbox_address(&self) -> Result<GrinboxAddress, Error> {
-        let public_key = self.get_grinbox_public_key()?;
-        Ok(GrinboxAddress::new(public_key, self.grinbox_domain.clone(), self.grinbox_port))
-    }
-
-    pub fn get_grinbox_public_key(&self) -> Result<PublicKey, Error> {
-        public_key_from_secret_key(&self.get_grinbox_secret_key()?)
+        GrinboxAddress::from_secret_key(&self.get_grinbox_secret_key()?, self.grinbox_domain.clone(), self.grinbox_port)
     }
 
     pub fn get_grinbox_secret_key(&self) -> Result<SecretKey, Error> {
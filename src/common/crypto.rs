@@ -12,6 +12,8 @@ use ring::{digest, pbkdf2};
 
 pub const GRINBOX_ADDRESS_VERSION_MAINNET: [u8; 2] = [1, 11];
 pub const GRINBOX_ADDRESS_VERSION_TESTNET: [u8; 2] = [1, 120];
+pub const ENCRYPTED_MESSAGE_VERSION: u8 = 1;
+pub const BOUND_ENCRYPTED_MESSAGE_VERSION: u8 = 2;
 
 pub trait Hex<T> {
     fn from_hex(str: &str) -> Result<T>;
@@ -101,6 +103,12 @@ pub fn public_key_from_secret_key(secret_key: &SecretKey) -> Result<PublicKey> {
     PublicKey::from_secret_key(&secp, secret_key).map_err(|_| Wallet713Error::Secp.into())
 }
 
+pub fn generate_secret_key() -> Result<SecretKey> {
+    let secp = Secp256k1::new();
+    let bytes: [u8; 32] = thread_rng().gen();
+    SecretKey::from_slice(&secp, &bytes).map_err(|_| Wallet713Error::Secp.into())
+}
+
 pub fn sign_challenge(challenge: &str, secret_key: &SecretKey) -> Result<Signature> {
     let mut hasher = Sha256::new();
     hasher.input(challenge.as_bytes());
@@ -114,18 +122,58 @@ pub fn verify_signature(challenge: &str, signature: &Signature, public_key: &Pub
     hasher.input(challenge.as_bytes());
     let message = Message::from_slice(hasher.result().as_slice())?;
     let secp = Secp256k1::new();
+    // secp256k1 checks the signature against the key without comparing any bytes, so there is
+    // nothing here that could leak through timing
     secp.verify(&message, signature, public_key).map_err(|_| Wallet713Error::Secp.into())
 }
 
+// messages from before the version tag was introduced are version 1
+fn default_encrypted_message_version() -> u8 {
+    ENCRYPTED_MESSAGE_VERSION
+}
+
+// a short tag of the key the message was sealed with, so a message encrypted to the wrong key
+// can be told apart from one that got corrupted on the way. it is derived from the shared key
+// rather than the receiver key, so it does not tell anyone else who the message is for.
+fn key_hint(key: &[u8]) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(b"wallet713 key hint");
+    ctx.update(key);
+    to_hex(ctx.finish().as_ref()[..4].to_vec())
+}
+
+fn associated_data(from: &str, to: &str) -> Vec<u8> {
+    format!("{}\n{}", from, to).into_bytes()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptedMessage {
+    #[serde(default = "default_encrypted_message_version")]
+    version: u8,
     encrypted_message: String,
     salt: String,
     nonce: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_hint: Option<String>,
+    // who sent the message to whom, from version 2 on they can not be changed unnoticed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
 }
 
 impl EncryptedMessage {
     pub fn new(message: String, receiver_public_key: &PublicKey, secret_key: &SecretKey) -> Result<EncryptedMessage> {
+        EncryptedMessage::seal(message, None, receiver_public_key, secret_key)
+    }
+
+    // binds the message to the addresses it goes between, so it can not be passed on to someone
+    // else or as coming from someone else. only receivers that know version 2 can read it.
+    pub fn new_bound(message: String, from: &str, to: &str, receiver_public_key: &PublicKey, secret_key: &SecretKey) -> Result<EncryptedMessage> {
+        EncryptedMessage::seal(message, Some((from, to)), receiver_public_key, secret_key)
+    }
+
+    fn seal(message: String, addresses: Option<(&str, &str)>, receiver_public_key: &PublicKey, secret_key: &SecretKey) -> Result<EncryptedMessage> {
         let secp = Secp256k1::new();
         let mut common_secret = receiver_public_key.clone();
         common_secret.mul_assign(&secp, secret_key).map_err(|_| Wallet713Error::Encryption)?;
@@ -143,35 +191,87 @@ impl EncryptedMessage {
         }
         let sealing_key = aead::SealingKey::new(&aead::CHACHA20_POLY1305, &key)
             .map_err(|_| Wallet713Error::Encryption)?;
-        aead::seal_in_place(&sealing_key, &nonce, &[], &mut enc_bytes, suffix_len)
+        let ad = addresses.map(|(from, to)| associated_data(from, to)).unwrap_or_default();
+        aead::seal_in_place(&sealing_key, &nonce, &ad, &mut enc_bytes, suffix_len)
             .map_err(|_| Wallet713Error::Encryption)?;
 
         Ok(EncryptedMessage {
+            version: match addresses {
+                Some(_) => BOUND_ENCRYPTED_MESSAGE_VERSION,
+                None => ENCRYPTED_MESSAGE_VERSION,
+            },
             encrypted_message: to_hex(enc_bytes),
             salt: to_hex(salt.to_vec()),
             nonce: to_hex(nonce.to_vec()),
+            key_hint: Some(key_hint(&key)),
+            from: addresses.map(|(from, _)| from.to_string()),
+            to: addresses.map(|(_, to)| to.to_string()),
         })
     }
 
-    pub fn decrypt(&self, sender_public_key: &PublicKey, secret_key: &SecretKey) -> Result<String> {
-        let mut encrypted_message = from_hex(self.encrypted_message.clone()).map_err(|_| Wallet713Error::Decryption)?;
-        let salt = from_hex(self.salt.clone()).map_err(|_| Wallet713Error::Decryption)?;
-        let nonce = from_hex(self.nonce.clone()).map_err(|_| Wallet713Error::Decryption)?;
+    pub fn decrypt(&self, sender_public_key: &PublicKey, secret_key: &SecretKey) -> ::std::result::Result<String, DecryptionError> {
+        match self.version {
+            ENCRYPTED_MESSAGE_VERSION => self.open(sender_public_key, secret_key, &[]),
+            BOUND_ENCRYPTED_MESSAGE_VERSION => match (&self.from, &self.to) {
+                (&Some(ref from), &Some(ref to)) => self.open(sender_public_key, secret_key, &associated_data(from, to)),
+                _ => Err(DecryptionError::MalformedCiphertext),
+            },
+            version => Err(DecryptionError::UnsupportedVersion(version)),
+        }
+    }
+
+    // also makes sure the message went from `from` to `to`, a `Wallet713Error::AddressBinding`
+    // if it did not. messages from before addresses were bound carry none, those are taken as
+    // they are unless `require_bound` is set, then they are a `Wallet713Error::UnboundSlate`.
+    // a version this client does not know is a `Wallet713Error::UnsupportedEncryptionVersion`,
+    // any other failure a `DecryptionError`.
+    pub fn decrypt_bound(&self, from: &str, to: &str, require_bound: bool, sender_public_key: &PublicKey, secret_key: &SecretKey) -> Result<String> {
+        if require_bound && self.version < BOUND_ENCRYPTED_MESSAGE_VERSION {
+            Err(Wallet713Error::UnboundSlate(from.to_string()))?;
+        }
+        if self.version >= BOUND_ENCRYPTED_MESSAGE_VERSION {
+            match (&self.from, &self.to) {
+                (&Some(ref bound_from), &Some(ref bound_to)) if bound_from == from && bound_to == to => {},
+                _ => Err(Wallet713Error::AddressBinding(from.to_string()))?,
+            }
+        }
+        match self.decrypt(sender_public_key, secret_key) {
+            Ok(x) => Ok(x),
+            Err(DecryptionError::UnsupportedVersion(version)) => Err(Wallet713Error::UnsupportedEncryptionVersion(version))?,
+            Err(e) => Err(e)?,
+        }
+    }
+
+    fn open(&self, sender_public_key: &PublicKey, secret_key: &SecretKey, ad: &[u8]) -> ::std::result::Result<String, DecryptionError> {
+        if self.encrypted_message.is_empty() || self.salt.is_empty() || self.nonce.is_empty() {
+            return Err(DecryptionError::MalformedCiphertext);
+        }
+        let mut encrypted_message = from_hex(self.encrypted_message.clone()).map_err(|_| DecryptionError::MalformedCiphertext)?;
+        let salt = from_hex(self.salt.clone()).map_err(|_| DecryptionError::MalformedCiphertext)?;
+        let nonce = from_hex(self.nonce.clone()).map_err(|_| DecryptionError::MalformedCiphertext)?;
+        if nonce.len() != 12 || encrypted_message.len() < aead::CHACHA20_POLY1305.tag_len() {
+            return Err(DecryptionError::MalformedCiphertext);
+        }
 
         let secp = Secp256k1::new();
         let mut common_secret = sender_public_key.clone();
-        common_secret.mul_assign(&secp, secret_key).map_err(|_| Wallet713Error::Decryption)?;
+        common_secret.mul_assign(&secp, secret_key).map_err(|_| DecryptionError::WrongKey)?;
         let common_secret_ser = common_secret.serialize_vec(&secp, true);
         let common_secret_slice = &common_secret_ser[1..33];
 
         let mut key = [0; 32];
         pbkdf2::derive(&digest::SHA512, 100, &salt, common_secret_slice, &mut key);
+        if let Some(ref hint) = self.key_hint {
+            if *hint != key_hint(&key) {
+                return Err(DecryptionError::WrongKey);
+            }
+        }
         let opening_key = aead::OpeningKey::new(&aead::CHACHA20_POLY1305, &key)
-            .map_err(|_| Wallet713Error::Decryption)?;
-        let decrypted_data = aead::open_in_place(&opening_key, &nonce, &[], 0, &mut encrypted_message)
-            .map_err(|_| Wallet713Error::Decryption)?;
+            .map_err(|_| DecryptionError::MalformedCiphertext)?;
+        let decrypted_data = aead::open_in_place(&opening_key, &nonce, ad, 0, &mut encrypted_message)
+            .map_err(|_| DecryptionError::AuthTagMismatch)?;
 
-        String::from_utf8(decrypted_data.to_vec()).map_err(|_| Wallet713Error::Decryption.into())
+        String::from_utf8(decrypted_data.to_vec()).map_err(|_| DecryptionError::MalformedCiphertext)
     }
 }
 
@@ -211,3 +311,138 @@ fn split_n(s: &str, n: usize) -> Vec<&str> {
         .map(|i| &s[2 * i..2 * i + n])
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::{self, Value};
+    use common::{Error, Wallet713Error};
+    use super::{EncryptedMessage, DecryptionError, ENCRYPTED_MESSAGE_VERSION, generate_secret_key, public_key_from_secret_key};
+
+    fn decryption_error(error: Error) -> Option<DecryptionError> {
+        error.downcast_ref::<DecryptionError>().cloned()
+    }
+
+    #[test]
+    fn reads_untagged_messages_as_version_one() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let message = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        let mut json: Value = serde_json::to_value(&message).unwrap();
+        json.as_object_mut().unwrap().remove("version");
+        let legacy: EncryptedMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(legacy.version, ENCRYPTED_MESSAGE_VERSION);
+        assert_eq!(legacy.decrypt(&sender_public_key, &receiver).unwrap(), "slate");
+    }
+
+    #[test]
+    fn rejects_unknown_encryption_versions() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let message = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        let mut json: Value = serde_json::to_value(&message).unwrap();
+        json["version"] = Value::from(3);
+        let unknown: EncryptedMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(unknown.decrypt(&sender_public_key, &receiver).unwrap_err(), DecryptionError::UnsupportedVersion(3));
+    }
+
+    #[test]
+    fn reports_unsupported_encryption_versions() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let mut message = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        message.version = 9;
+        assert_eq!(message.decrypt(&sender_public_key, &receiver).unwrap_err(), DecryptionError::UnsupportedVersion(9));
+        let error = message.decrypt_bound("alice", "bob", false, &sender_public_key, &receiver).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::UnsupportedEncryptionVersion(9)) => {},
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn can_tell_decryption_failures_apart() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let other = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let message = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        assert_eq!(message.decrypt(&sender_public_key, &receiver).unwrap(), "slate");
+        assert_eq!(message.decrypt(&sender_public_key, &other).unwrap_err(), DecryptionError::WrongKey);
+
+        let mut tampered = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        let first = u8::from_str_radix(&tampered.encrypted_message[..2], 16).unwrap() ^ 1;
+        tampered.encrypted_message = format!("{:02x}{}", first, &tampered.encrypted_message[2..]);
+        assert_eq!(tampered.decrypt(&sender_public_key, &receiver).unwrap_err(), DecryptionError::AuthTagMismatch);
+
+        let mut truncated = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        truncated.encrypted_message.truncate(4);
+        assert_eq!(truncated.decrypt(&sender_public_key, &receiver).unwrap_err(), DecryptionError::MalformedCiphertext);
+    }
+
+    #[test]
+    fn key_hints_do_not_link_messages_to_the_receiver() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let first = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        let second = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        assert!(first.key_hint.is_some());
+        assert_ne!(first.key_hint, second.key_hint);
+    }
+
+    #[test]
+    fn binds_messages_to_their_addresses() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let message = EncryptedMessage::new_bound("slate".to_string(), "alice", "bob", &receiver_public_key, &sender).unwrap();
+        assert_eq!(message.decrypt_bound("alice", "bob", false, &sender_public_key, &receiver).unwrap(), "slate");
+        for &(from, to) in &[("alice", "carol"), ("mallory", "bob")] {
+            let error = message.decrypt_bound(from, to, false, &sender_public_key, &receiver).unwrap_err();
+            match error.downcast_ref::<Wallet713Error>() {
+                Some(&Wallet713Error::AddressBinding(ref claimed)) => assert_eq!(claimed, from),
+                _ => panic!("unexpected error: {}", error),
+            }
+        }
+
+        // the addresses travel in the clear, but changing them breaks the authentication
+        let mut rerouted = EncryptedMessage::new_bound("slate".to_string(), "alice", "bob", &receiver_public_key, &sender).unwrap();
+        rerouted.to = Some("carol".to_string());
+        assert_eq!(decryption_error(rerouted.decrypt_bound("alice", "carol", false, &sender_public_key, &receiver).unwrap_err()), Some(DecryptionError::AuthTagMismatch));
+
+        let unbound = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        assert_eq!(unbound.decrypt_bound("alice", "bob", false, &sender_public_key, &receiver).unwrap(), "slate");
+    }
+
+    #[test]
+    fn can_require_bound_messages() {
+        let sender = generate_secret_key().unwrap();
+        let receiver = generate_secret_key().unwrap();
+        let sender_public_key = public_key_from_secret_key(&sender).unwrap();
+        let receiver_public_key = public_key_from_secret_key(&receiver).unwrap();
+
+        let bound = EncryptedMessage::new_bound("slate".to_string(), "alice", "bob", &receiver_public_key, &sender).unwrap();
+        assert_eq!(bound.decrypt_bound("alice", "bob", true, &sender_public_key, &receiver).unwrap(), "slate");
+
+        let unbound = EncryptedMessage::new("slate".to_string(), &receiver_public_key, &sender).unwrap();
+        let error = unbound.decrypt_bound("alice", "bob", true, &sender_public_key, &receiver).unwrap_err();
+        match error.downcast_ref::<Wallet713Error>() {
+            Some(&Wallet713Error::UnboundSlate(ref from)) => assert_eq!(from, "alice"),
+            _ => panic!("unexpected error: {}", error),
+        }
+    }
+}
@@ -25,6 +25,8 @@ pub enum Wallet713Error {
     ContactAlreadyExists(String),
     #[fail(display = "could not find contact named `{}`!", 0)]
     ContactNotFound(String),
+    #[fail(display = "`@{}` is not one of your contacts!", 0)]
+    UnknownContact(String),
     #[fail(display = "invalid character!")]
     InvalidBase58Character(char, usize),
     #[fail(display = "invalid length!")]
@@ -43,6 +45,8 @@ pub enum Wallet713Error {
     MissingAddressType(String),
     #[fail(display = "could not parse `{}` to a grinbox address!", 0)]
     GrinboxAddressParsingError(String),
+    #[fail(display = "invalid grinbox address: {}", reason)]
+    InvalidGrinboxAddress { reason: String },
     #[fail(display = "could not parse `{}` to a keybase address!", 0)]
     KeybaseAddressParsingError(String),
     #[fail(display = "could not send keybase message!")]
@@ -57,6 +61,24 @@ pub enum Wallet713Error {
     GrinWalletPostError,
     #[fail(display = "keybase not found! consider installing keybase locally first.")]
     KeybaseNotFound,
+    #[fail(display = "already subscribed to grinbox!")]
+    AlreadySubscribed,
+    #[fail(display = "grinbox relay rejected the subscription!")]
+    SubscribeRejected,
+    #[fail(display = "certificate of `{}` does not match the pinned fingerprint!", 0)]
+    CertificatePinMismatch(String),
+    #[fail(display = "tls handshake with `{}` failed: {}", 0, 1)]
+    TlsHandshake(String, String),
+    #[fail(display = "timed out connecting to `{}`!", 0)]
+    ConnectTimeout(String),
+    #[fail(display = "could not confirm delivery to `{}`!", 0)]
+    DeliveryNotConfirmed(String),
+    #[fail(display = "publisher does not support the requested delivery guarantee!")]
+    DeliveryGuaranteeUnsupported,
+    #[fail(display = "too many slates posted, try again later!")]
+    RateLimited,
+    #[fail(display = "timed out waiting for a slate!")]
+    ReceiveTimeout,
     #[fail(display = "grinbox websocket terminated unexpectedly!")]
     GrinboxWebsocketAbnormalTermination,
     #[fail(display = "rejecting invoice as auto invoice acceptance is turned off!")]
@@ -67,10 +89,54 @@ pub enum Wallet713Error {
     HasListener,
     #[fail(display = "wallet already unlocked")]
     WalletAlreadyUnlocked,
+    #[fail(display = "relay does not support encrypted subscriptions!")]
+    EncryptedSubscribeUnsupported,
+    #[fail(display = "relay associated the connection with a different address!")]
+    ChannelBindingMismatch,
+    #[fail(display = "unsupported slate version `{}`!", 0)]
+    UnsupportedSlateVersion(u64),
+    #[fail(display = "slate version `{}` is not supported, expected one of [{}]!", 0, 1)]
+    SlateVersionMismatch(u64, String),
+    #[fail(display = "slate [{}] was expected from {} but arrived from {}!", 0, 1, 2)]
+    SlateSenderMismatch(String, String, String),
+    #[fail(display = "missed slates from the relay, expected sequence {} but got {}!", expected, got)]
+    SequenceGap { expected: u64, got: u64 },
+    #[fail(display = "the relay ran out of sequence numbers!")]
+    SequenceOverflow,
+    #[fail(display = "all {} post connections stayed busy, giving up!", 0)]
+    PostConnectionsExhausted(usize),
+    #[fail(display = "grinbox address `{}` has no usable public key to subscribe with!", 0)]
+    MissingPublicKey(String),
+    #[fail(display = "the memo is longer than the {} characters allowed!", 0)]
+    MemoTooLong(usize),
+    #[fail(display = "the relay closed the connection: {}", 0)]
+    RelayClosed(String),
+    #[fail(display = "could not connect through the proxy: {}", 0)]
+    ProxyConnect(String),
+    #[fail(display = "could not reconnect to grinbox within {} ms, giving up!", 0)]
+    ReconnectWindowExceeded(u64),
+    #[fail(display = "could not resolve `{}` to a grinbox address!", 0)]
+    AddressResolutionFailed(String),
+    #[fail(display = "could not deliver to webhook: {}", 0)]
+    WebhookFailed(String),
+    #[fail(display = "there is no failed post to retry!")]
+    NoPostToRetry,
+    #[fail(display = "could not downgrade slate to version `{}`!", 0)]
+    SlateDowngradeFailed(u64),
+    #[fail(display = "invalid slate: {}!", 0)]
+    InvalidSlate(String),
     #[fail(display = "unable to encrypt message")]
     Encryption,
-    #[fail(display = "unable to decrypt message")]
-    Decryption,
+    #[fail(display = "slate claiming to be from {} was sent by or to someone else!", 0)]
+    AddressBinding(String),
+    #[fail(display = "slate from {} is not bound to its sender and recipient!", 0)]
+    UnboundSlate(String),
+    #[fail(display = "unable to compress slate")]
+    Compression,
+    #[fail(display = "unable to decompress slate")]
+    Decompression,
+    #[fail(display = "unsupported encryption version `{}`!", 0)]
+    UnsupportedEncryptionVersion(u8),
 }
 
 impl From<KeychainError> for Wallet713Error {
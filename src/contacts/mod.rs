@@ -8,6 +8,8 @@ mod test {
     use super::{Contact, Address, AddressType, GrinboxAddress, KeybaseAddress, LMDBBackend, AddressBook};
     use std::str::FromStr;
     use uuid::Uuid;
+    use common::Wallet713Error;
+    use common::crypto::{SecretKey, Hex};
 
     #[test]
     fn can_add_and_get_contact_no_duplicates() {
@@ -83,4 +85,73 @@ mod test {
         assert_eq!(None, address.port);
         assert_eq!(format!("{}", address), address_str);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn can_validate_grinbox_address() {
+        let address_str = "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@grinbox.io:5555";
+        let address = GrinboxAddress::from_str(address_str).unwrap();
+        assert!(address.validate().is_ok());
+
+        let mut bad_port = address.clone();
+        bad_port.port = Some(0);
+        assert!(bad_port.validate().is_err());
+
+        let mut bad_domain = address.clone();
+        bad_domain.domain = "grinbox..io".to_string();
+        assert!(bad_domain.validate().is_err());
+
+        let mut bad_key = address.clone();
+        bad_key.public_key = "xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2Vpn1".to_string();
+        assert!(bad_key.validate().is_err());
+    }
+
+    #[test]
+    fn can_normalize_grinbox_address() {
+        let address = GrinboxAddress::from_str(" grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@GrinBox.IO:443 ").unwrap();
+        assert_eq!("grinbox.io", address.domain);
+        assert_eq!(Some(443), address.port);
+        assert_eq!("grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU", address.to_string());
+        // a broker with another default port dials a different relay for the address without one
+        assert_ne!(GrinboxAddress::from_str("grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU").unwrap(), address);
+        assert_eq!(GrinboxAddress::from_address(&address).unwrap().port, Some(443));
+
+        for address_str in &[
+            "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU",
+            "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@Example.com",
+            "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@example.com:5555",
+        ] {
+            let address = GrinboxAddress::from_str(address_str).unwrap();
+            assert_eq!(GrinboxAddress::from_str(&address.to_string()).unwrap(), address);
+        }
+        assert!(GrinboxAddress::from_str("grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@grinbox.io:").is_err());
+    }
+
+    #[test]
+    fn rejects_grinbox_ports_out_of_range() {
+        let address_str = "grinbox://xd6A7NwpB2yDevoShkZLPorZB2h7Aivf9JyjkngKywgzrog2VpnU@grinbox.io";
+        assert_eq!(None, GrinboxAddress::from_str(address_str).unwrap().port);
+        assert_eq!(Some(443), GrinboxAddress::from_str(&format!("{}:443", address_str)).unwrap().port);
+        assert_eq!(Some(1), GrinboxAddress::from_str(&format!("{}:1", address_str)).unwrap().port);
+        assert_eq!(Some(65535), GrinboxAddress::from_str(&format!("{}:65535", address_str)).unwrap().port);
+
+        for port in &["0", "00", "65536", "99999999999999999999999"] {
+            let error = GrinboxAddress::from_str(&format!("{}:{}", address_str, port)).unwrap_err();
+            match error.downcast_ref::<Wallet713Error>() {
+                Some(&Wallet713Error::InvalidGrinboxAddress { .. }) => {},
+                _ => panic!("port {} was not rejected as invalid: {}", port, error),
+            }
+        }
+    }
+
+    #[test]
+    fn can_derive_grinbox_address_from_secret_key() {
+        // the key 1 belongs to the generator point of secp256k1
+        let secret_key = SecretKey::from_hex("0000000000000000000000000000000000000000000000000000000000000001").unwrap();
+        let address = GrinboxAddress::from_secret_key(&secret_key, "GrinBox.io".to_string(), Some(443)).unwrap();
+        assert_eq!("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798", address.public_key().unwrap().to_hex());
+        assert_eq!("grinbox.io", address.domain);
+        assert_eq!(Some(443), address.port);
+        assert!(address.validate().is_ok());
+        assert_eq!(GrinboxAddress::from_str(&format!("{}@grinbox.io:443", address.stripped())).unwrap(), address);
+    }
+}
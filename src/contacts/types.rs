@@ -4,7 +4,7 @@ use regex::Regex;
 use grin_core::global::is_mainnet;
 
 use common::{Error, Wallet713Error};
-use common::crypto::{PublicKey, Base58, GRINBOX_ADDRESS_VERSION_MAINNET, GRINBOX_ADDRESS_VERSION_TESTNET};
+use common::crypto::{PublicKey, SecretKey, Base58, public_key_from_secret_key, GRINBOX_ADDRESS_VERSION_MAINNET, GRINBOX_ADDRESS_VERSION_TESTNET};
 
 const ADDRESS_REGEX: &str = r"^((?P<address_type>keybase|grinbox)://).+$";
 const GRINBOX_ADDRESS_REGEX: &str = r"^(grinbox://)?(?P<public_key>[123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz]{52})(@(?P<domain>[a-zA-Z0-9\.]+)(:(?P<port>[0-9]*))?)?$";
@@ -21,6 +21,9 @@ pub trait Address: Debug + Display {
     fn from_str(s: &str) -> Result<Self, Error> where Self: Sized;
     fn address_type(&self) -> AddressType;
     fn stripped(&self) -> String;
+    fn as_grinbox(&self) -> Option<&GrinboxAddress> {
+        None
+    }
 }
 
 impl Address {
@@ -100,6 +103,24 @@ impl AddressBook {
     pub fn contact_iter(&self) -> Box<Iterator<Item=Contact>> {
         self.backend.contact_iter()
     }
+
+    // a recipient is either an address or the name of a contact prefixed with `@`
+    pub fn resolve(&mut self, recipient: &str) -> Result<Box<Address>, Error> {
+        let recipient = recipient.trim();
+        let address = match recipient.starts_with("@") {
+            true => self.get_contact(&recipient[1..])
+                .map_err(|_| Wallet713Error::UnknownContact(recipient[1..].to_string()))?
+                .get_address()
+                .to_string(),
+            false => recipient.to_string(),
+        };
+
+        // try parse as a general address and fallback to grinbox address
+        match Address::parse(&address) {
+            Ok(address) => Ok(address),
+            Err(e) => Ok(Box::new(GrinboxAddress::from_str(&address).map_err(|_| e)?)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -183,7 +204,7 @@ pub fn version_bytes() -> Vec<u8> {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct GrinboxAddress {
     pub public_key: String,
     pub domain: String,
@@ -196,16 +217,66 @@ impl GrinboxAddress {
             public_key: public_key.to_base58_check(version_bytes()),
             domain,
             port
+        }.normalized()
+    }
+
+    // the display form leaves out a default port, so going through it would lose a port that
+    // was given explicitly
+    pub fn from_address(address: &Address) -> Result<Self, Error> {
+        match address.as_grinbox() {
+            Some(address) => Ok(address.clone()),
+            None => Self::from_str(&address.to_string()),
+        }
+    }
+
+    // the address a key receives slates on, without having to set up a broker for it
+    pub fn from_secret_key(secret_key: &SecretKey, domain: String, port: Option<u16>) -> Result<Self, Error> {
+        let public_key = public_key_from_secret_key(secret_key)?;
+        Ok(Self::new(public_key, domain, port))
+    }
+
+    // the canonical form every address is compared and signed in: lowercase domain. a port
+    // given explicitly is kept, even the default one, so it still wins over a broker's default
+    // port. only the display leaves the default port out, so `:443` and no port are different
+    // addresses that display the same.
+    pub fn normalized(self) -> Self {
+        Self {
+            public_key: self.public_key.trim().to_string(),
+            domain: self.domain.trim().to_lowercase(),
+            port: self.port,
         }
     }
 
+    // the port as displayed, where the default port is the same as none
+    fn display_port(&self) -> Option<u16> {
+        self.port.filter(|port| *port != DEFAULT_GRINBOX_PORT)
+    }
+
     pub fn public_key(&self) -> Result<PublicKey, Error> {
         PublicKey::from_base58_check(&self.public_key, version_bytes())
     }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| -> Error {
+            Wallet713Error::InvalidGrinboxAddress { reason: format!("`{}` {}", self, reason) }.into()
+        };
+        if self.domain.is_empty() {
+            return Err(invalid("is missing a domain"));
+        }
+        if self.domain.starts_with('.') || self.domain.ends_with('.') || self.domain.contains("..") {
+            return Err(invalid("has a malformed domain"));
+        }
+        if self.port == Some(0) {
+            return Err(invalid("has an invalid port"));
+        }
+        self.public_key().map_err(|_| invalid("has a public key that could not be decoded"))?;
+        Ok(())
+    }
 }
 
 impl Address for GrinboxAddress {
     fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
         let re = Regex::new(GRINBOX_ADDRESS_REGEX).unwrap();
         let captures = re.captures(s);
         if captures.is_none() {
@@ -215,7 +286,10 @@ impl Address for GrinboxAddress {
         let captures = captures.unwrap();
         let public_key = captures.name("public_key").unwrap().as_str().to_string();
         let domain = captures.name("domain").map(|m| m.as_str().to_string()).unwrap_or(DEFAULT_GRINBOX_DOMAIN.to_string());
-        let port = captures.name("port").map(|m| u16::from_str_radix(m.as_str(), 10).unwrap());
+        let port = match captures.name("port") {
+            Some(m) => Some(parse_port(s, m.as_str())?),
+            None => None,
+        };
 
         PublicKey::from_base58_check(&public_key, version_bytes())?;
 
@@ -225,7 +299,7 @@ impl Address for GrinboxAddress {
             port
         };
 
-        Ok(address)
+        Ok(address.normalized())
     }
 
     fn address_type(&self) -> AddressType {
@@ -235,15 +309,30 @@ impl Address for GrinboxAddress {
     fn stripped(&self) -> String {
         format!("{}", self)[10..].to_string()
     }
+
+    fn as_grinbox(&self) -> Option<&GrinboxAddress> {
+        Some(self)
+    }
+}
+
+// an empty port is a typo, a port out of range can never be connected to
+fn parse_port(address: &str, port: &str) -> Result<u16, Error> {
+    if port.is_empty() {
+        Err(Wallet713Error::GrinboxAddressParsingError(address.to_string()))?;
+    }
+    match u64::from_str_radix(port, 10) {
+        Ok(port) if port >= 1 && port <= u64::from(u16::max_value()) => Ok(port as u16),
+        _ => Err(Wallet713Error::InvalidGrinboxAddress { reason: format!("`{}` has a port outside 1..=65535", address) })?,
+    }
 }
 
 impl Display for GrinboxAddress {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "grinbox://{}", self.public_key)?;
-        if self.domain != DEFAULT_GRINBOX_DOMAIN || (self.port.is_some() && self.port.unwrap() != DEFAULT_GRINBOX_PORT) {
+        if self.domain != DEFAULT_GRINBOX_DOMAIN || self.display_port().is_some() {
             write!(f, "@{}", self.domain)?;
-            if self.port.is_some() && self.port.unwrap() != DEFAULT_GRINBOX_PORT {
-                write!(f, ":{}", self.port.unwrap())?;
+            if let Some(port) = self.display_port() {
+                write!(f, ":{}", port)?;
             }
         }
         Ok(())
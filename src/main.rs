@@ -1,6 +1,7 @@
 #[macro_use] extern crate failure;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
+#[macro_use] extern crate log;
 extern crate serde;
 extern crate clap;
 extern crate colored;
@@ -16,6 +17,10 @@ extern crate ring;
 extern crate uuid;
 extern crate regex;
 extern crate rustyline;
+extern crate openssl;
+extern crate mio;
+extern crate url;
+extern crate flate2;
 
 extern crate grin_wallet;
 extern crate grin_keychain;
@@ -27,7 +32,8 @@ use failure::Error;
 use std::sync::{Arc, Mutex};
 use std::io::{Read, Write};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use clap::{App, Arg, ArgMatches};
 use colored::*;
 use rustyline::Editor;
@@ -49,6 +55,8 @@ use cli::Parser;
 use contacts::{Address, AddressType, GrinboxAddress, Contact, AddressBook, LMDBBackend};
 
 const CLI_HISTORY_PATH: &str = ".history";
+const GRINBOX_STATE_PATH: &str = "grinbox_state.json";
+const GRINBOX_STOP_TIMEOUT_SECS: u64 = 10;
 
 fn do_config(args: &ArgMatches, chain: &Option<ChainTypes>, silent: bool, new_address_index: Option<u32>) -> Result<Wallet713Config, Error> {
 	let mut config;
@@ -161,13 +169,14 @@ fn welcome(args: &ArgMatches) -> Result<Wallet713Config, Error> {
 
 use std::borrow::Borrow;
 use grin_core::libtx::slate::Slate;
-use broker::{GrinboxSubscriber, GrinboxPublisher, KeybasePublisher, KeybaseSubscriber, SubscriptionHandler, Subscriber, Publisher, CloseReason};
+use broker::{BrokerConfig, GrinboxSubscriber, GrinboxPublisher, KeybasePublisher, KeybaseSubscriber, SubscriptionHandler, Subscriber, Publisher, CloseReason, ConnectionState, JsonCodec, SlateCodec, WebhookHandler, SenderFilter, Subscription, DeliveryGuarantee, relay_port};
 
 struct Controller {
     name: String,
     wallet: Arc<Mutex<Wallet>>,
     address_book: Arc<Mutex<AddressBook>>,
     publisher: Box<Publisher + Send>,
+    webhook: Option<WebhookHandler>,
 }
 
 impl Controller {
@@ -177,9 +186,16 @@ impl Controller {
             wallet,
             address_book,
             publisher,
+            webhook: None,
         })
     }
 
+    // also tells `webhook` about everything the listener sees
+    pub fn with_webhook(mut self, webhook: WebhookHandler) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+
     fn process_incoming_slate(&self, slate: &mut Slate) -> Result<bool, Error> {
         if slate.num_participants > slate.participant_data.len() {
             //TODO: this needs to be changed to properly figure out if this slate is an invoice or a send
@@ -198,10 +214,16 @@ impl Controller {
 
 impl SubscriptionHandler for Controller {
     fn on_open(&self) {
+        if let Some(ref webhook) = self.webhook {
+            webhook.on_open();
+        }
         cli_message!("listener started for [{}]", self.name.bright_green());
     }
 
     fn on_slate(&self, from: &Address, slate: &mut Slate) {
+        if let Some(ref webhook) = self.webhook {
+            webhook.on_slate(from, slate);
+        }
         let mut display_from = from.stripped();
         if let Ok(contact) = self.address_book.lock().unwrap().get_contact_by_address(&display_from) {
             display_from = contact.get_name().to_string();
@@ -247,22 +269,72 @@ impl SubscriptionHandler for Controller {
     }
 
     fn on_close(&self, reason: CloseReason) {
+        if let Some(ref webhook) = self.webhook {
+            webhook.on_close(reason.clone());
+        }
         match reason {
             CloseReason::Normal => cli_message!("listener [{}] stopped", self.name.bright_green()),
             CloseReason::Abnormal(_) => cli_message!("{}: listener [{}] stopped unexpectedly", "ERROR".bright_red(), self.name.bright_green()),
+            CloseReason::ProtocolError(description) => cli_message!("{}: listener [{}] was closed by the relay: {}", "ERROR".bright_red(), self.name.bright_green(), description),
         }
     }
 
     fn on_dropped(&self) {
+        if let Some(ref webhook) = self.webhook {
+            webhook.on_dropped();
+        }
         cli_message!("{}: listener [{}] lost connection. it will keep trying to restore connection in the background.", "WARNING".bright_yellow(), self.name.bright_green())
     }
 
     fn on_reestablished(&self) {
+        if let Some(ref webhook) = self.webhook {
+            webhook.on_reestablished();
+        }
         cli_message!("{}: listener [{}] reestablished connection.", "INFO".bright_blue(), self.name.bright_green())
     }
+
+    fn on_capability_downgrade(&self, lost: Vec<String>) {
+        cli_message!("{}: relay for listener [{}] no longer supports [{}]", "WARNING".bright_yellow(), self.name.bright_green(), lost.join(", ").bright_green())
+    }
+}
+
+fn grinbox_broker_config(config: &Wallet713Config) -> BrokerConfig {
+    let mut broker_config = BrokerConfig::new(config.grinbox_e2e_encryption());
+    broker_config.pinned_certificate = config.grinbox_pinned_certificate.clone();
+    broker_config.ca_bundle = config.grinbox_ca_bundle.clone();
+    broker_config.fallback_relays = config.grinbox_fallback_relays.clone().unwrap_or_default();
+    broker_config.pretty_slates = config.pretty_slates();
+    broker_config
+}
+
+// slates written to files are laid out like the ones posted to grinbox
+fn slate_to_file(config: &Wallet713Config, slate: &Slate, file: &mut File) -> Result<(), Error> {
+    let slate = JsonCodec::new(None).with_pretty_printing(config.pretty_slates()).encode(slate)?;
+    file.write_all(slate.as_bytes())?;
+    Ok(())
+}
+
+// allowed senders take precedence, there is no point in blocking anyone on top of them
+fn grinbox_sender_filter(config: &Wallet713Config) -> Result<Option<SenderFilter>, Error> {
+    let parse = |addresses: &Vec<String>| -> Result<Vec<GrinboxAddress>, Error> {
+        addresses.iter().map(|address| GrinboxAddress::from_str(address)).collect()
+    };
+    Ok(match (&config.grinbox_allowed_senders, &config.grinbox_blocked_senders) {
+        (&Some(ref allowed), _) => Some(SenderFilter::Allow(parse(allowed)?)),
+        (&None, &Some(ref blocked)) => Some(SenderFilter::Block(parse(blocked)?)),
+        (&None, &None) => None,
+    })
+}
+
+fn seconds_since(time: SystemTime) -> u64 {
+    SystemTime::now().duration_since(time).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+fn grinbox_state_path(config: &Wallet713Config) -> Result<PathBuf, Error> {
+    Ok(config.get_data_path()?.join(GRINBOX_STATE_PATH))
 }
 
-fn start_grinbox_listener(config: &Wallet713Config, wallet: Arc<Mutex<Wallet>>, address_book: Arc<Mutex<AddressBook>>) -> Result<(GrinboxPublisher, GrinboxSubscriber), Error> {
+fn start_grinbox_listener(config: &Wallet713Config, wallet: Arc<Mutex<Wallet>>, address_book: Arc<Mutex<AddressBook>>) -> Result<(GrinboxPublisher, GrinboxSubscriber, Subscription), Error> {
     // make sure wallet is not locked, if it is try to unlock with no passphrase
     if let Ok(mut wallet) = wallet.lock() {
         if wallet.is_locked() {
@@ -273,20 +345,41 @@ fn start_grinbox_listener(config: &Wallet713Config, wallet: Arc<Mutex<Wallet>>,
     cli_message!("starting grinbox listener...");
     let grinbox_address = config.get_grinbox_address()?;
     let grinbox_secret_key = config.get_grinbox_secret_key()?;
-    let grinbox_publisher = GrinboxPublisher::new(&grinbox_address, &grinbox_secret_key, config.grinbox_e2e_encryption())?;
-    let grinbox_subscriber = GrinboxSubscriber::new(&grinbox_address, &grinbox_secret_key, config.grinbox_e2e_encryption()).expect("could not start grinbox subscriber!");
-    let cloned_publisher = grinbox_publisher.clone();
-    let mut cloned_subscriber = grinbox_subscriber.clone();
-    std::thread::spawn(move || {
-        let controller = Controller::new(
-            &grinbox_address.stripped(),
-            wallet.clone(),
-            address_book.clone(),
-            Box::new(cloned_publisher),
-        ).expect("could not start grinbox controller!");
-        cloned_subscriber.start(Box::new(controller)).expect("something went wrong!");
-    });
-    Ok((grinbox_publisher, grinbox_subscriber))
+    let mut grinbox_publisher = GrinboxPublisher::with_config(&grinbox_address, &grinbox_secret_key, grinbox_broker_config(config))?;
+    let mut grinbox_subscriber = GrinboxSubscriber::with_config(&grinbox_address, &grinbox_secret_key, grinbox_broker_config(config)).expect("could not start grinbox subscriber!");
+    if let Some(filter) = grinbox_sender_filter(config)? {
+        grinbox_subscriber.set_sender_filter(filter);
+    }
+    grinbox_publisher.set_local_subscriber(&grinbox_subscriber);
+    grinbox_publisher.set_address_book(address_book.clone());
+    let state_path = grinbox_state_path(config)?;
+    if state_path.exists() {
+        if let Err(e) = grinbox_publisher.load_state(&state_path) {
+            cli_message!("{}: could not restore the grinbox state: {}", "WARNING".bright_yellow(), e);
+        }
+    }
+    let mut controller = Controller::new(
+        &grinbox_address.stripped(),
+        wallet.clone(),
+        address_book.clone(),
+        Box::new(grinbox_publisher.clone()),
+    ).expect("could not start grinbox controller!");
+    if let Some(ref url) = config.grinbox_webhook_url {
+        controller = controller.with_webhook(WebhookHandler::new(url)?.with_clock(grinbox_subscriber.clock()));
+    }
+    let subscription = grinbox_subscriber.start(Box::new(controller))?;
+    Ok((grinbox_publisher, grinbox_subscriber, subscription))
+}
+
+// lets the slates that already arrived be processed, then keeps what the publisher needs to
+// carry on after a restart
+fn stop_grinbox_listener(config: &Wallet713Config, publisher: &GrinboxPublisher, subscriber: &GrinboxSubscriber, subscription: &Subscription) -> Result<(), Error> {
+    let timeout = Duration::from_secs(GRINBOX_STOP_TIMEOUT_SECS);
+    if !subscriber.stop_graceful(timeout) {
+        cli_message!("{}: grinbox listener stopped before all received slates were processed", "WARNING".bright_yellow());
+    }
+    subscription.wait(Some(timeout));
+    publisher.save_state(&grinbox_state_path(config)?)
 }
 
 fn start_keybase_listener(config: &Wallet713Config, wallet: Arc<Mutex<Wallet>>, address_book: Arc<Mutex<AddressBook>>) -> Result<(KeybasePublisher, KeybaseSubscriber), Error> {
@@ -337,7 +430,7 @@ fn main() {
     let wallet = Wallet::new(config.max_auto_accept_invoice);
     let wallet = Arc::new(Mutex::new(wallet));
 
-    let mut grinbox_broker: Option<(GrinboxPublisher, GrinboxSubscriber)> = None;
+    let mut grinbox_broker: Option<(GrinboxPublisher, GrinboxSubscriber, Subscription)> = None;
     let mut keybase_broker: Option<(KeybasePublisher, KeybaseSubscriber)> = None;
 
     let account = matches.value_of("account");
@@ -416,9 +509,15 @@ fn main() {
     if let Some(path) = Path::new(wallet713_home_path).join(CLI_HISTORY_PATH).to_str() {
         rl.save_history(path).is_ok();
     }
+
+    if let Some((publisher, subscriber, subscription)) = grinbox_broker.take() {
+        if let Err(e) = stop_grinbox_listener(&config, &publisher, &subscriber, &subscription) {
+            cli_message!("{}: {}", "ERROR".bright_red(), e);
+        }
+    }
 }
 
-fn derive_address_key(config: &mut Wallet713Config, wallet: Arc<Mutex<Wallet>>, grinbox_broker: &mut Option<(GrinboxPublisher, GrinboxSubscriber)>) -> Result<(), Error> {
+fn derive_address_key(config: &mut Wallet713Config, wallet: Arc<Mutex<Wallet>>, grinbox_broker: &mut Option<(GrinboxPublisher, GrinboxSubscriber, Subscription)>) -> Result<(), Error> {
     if grinbox_broker.is_some() {
         return Err(Wallet713Error::HasListener.into());
     }
@@ -429,7 +528,7 @@ fn derive_address_key(config: &mut Wallet713Config, wallet: Arc<Mutex<Wallet>>,
     Ok(())
 }
 
-fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wallet>>, address_book: Arc<Mutex<AddressBook>>, keybase_broker: &mut Option<(KeybasePublisher, KeybaseSubscriber)>, grinbox_broker: &mut Option<(GrinboxPublisher, GrinboxSubscriber)>) -> Result<bool, Error> {
+fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wallet>>, address_book: Arc<Mutex<AddressBook>>, keybase_broker: &mut Option<(KeybasePublisher, KeybaseSubscriber)>, grinbox_broker: &mut Option<(GrinboxPublisher, GrinboxSubscriber, Subscription)>) -> Result<bool, Error> {
     let matches = Parser::parse(command)?;
     match matches.subcommand_name() {
         Some("config") => {
@@ -509,14 +608,13 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             let keybase = matches.subcommand_matches("listen").unwrap().is_present("keybase");
             if grinbox || !keybase {
                 let is_running = match grinbox_broker {
-                    Some((_, subscriber)) => subscriber.is_running(),
+                    Some((_, subscriber, _)) => subscriber.is_running(),
                     _ => false
                 };
                 if is_running {
                     Err(Wallet713Error::AlreadyListening("grinbox".to_string()))?
                 } else {
-                    let (publisher, subscriber) = start_grinbox_listener(config, wallet.clone(), address_book.clone())?;
-                    *grinbox_broker = Some((publisher, subscriber));
+                    *grinbox_broker = Some(start_grinbox_listener(config, wallet.clone(), address_book.clone())?);
                 }
             }
             if keybase {
@@ -537,15 +635,14 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             let keybase = matches.subcommand_matches("stop").unwrap().is_present("keybase");
             if grinbox || !keybase {
                 let is_running = match grinbox_broker {
-                    Some((_, subscriber)) => subscriber.is_running(),
+                    Some((_, subscriber, _)) => subscriber.is_running(),
                     _ => false
                 };
                 if is_running {
                     cli_message!("stopping grinbox listener...");
-                    if let Some((_, subscriber)) = grinbox_broker {
-                        subscriber.stop();
+                    if let Some((publisher, subscriber, subscription)) = grinbox_broker.take() {
+                        stop_grinbox_listener(config, &publisher, &subscriber, &subscription)?;
                     };
-                    *grinbox_broker = None;
                 } else {
                     Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
                 }
@@ -606,7 +703,7 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             let mut slate: Slate = serde_json::from_str(&slate)?;
             let mut file = File::create(&format!("{}.{}", input, "response"))?;
             wallet.lock().unwrap().process_sender_initiated_slate(&mut slate)?;
-            file.write_all(serde_json::to_string(&slate).unwrap().as_bytes())?;
+            slate_to_file(config, &slate, &mut file)?;
         },
         Some("finalize") => {
             let args = matches.subcommand_matches("finalize").unwrap();
@@ -622,6 +719,7 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             let to = args.value_of("to");
             let input = args.value_of("file");
             let message = args.value_of("message").map(|s| s.to_string());
+            let memo = args.value_of("memo");
 
             let change_outputs = args.value_of("change-outputs").unwrap_or("1");
             let change_outputs = usize::from_str_radix(change_outputs, 10)
@@ -637,26 +735,12 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             if let Some(input) = input {
                 let mut file = File::create(input)?;
                 let slate = wallet.lock().unwrap().initiate_send_tx(amount, 10, "smallest", change_outputs, 500, message)?;
-                file.write_all(serde_json::to_string(&slate).unwrap().as_bytes())?;
+                slate_to_file(config, &slate, &mut file)?;
                 return Ok(true)
             }
 
-            let mut to = to.unwrap().to_string();
-            if to.starts_with("@") {
-                let contact = address_book.lock().unwrap().get_contact(&to[1..])?;
-                to = contact.get_address().to_string();
-            }
-
-            // try parse as a general address and fallback to grinbox address
-            let address = Address::parse(&to);
-            let address: Result<Box<Address>, Error> = match address {
-                Ok(address) => Ok(address),
-                Err(e) => {
-                    Ok(Box::new(GrinboxAddress::from_str(&to).map_err(|_| e)?) as Box<Address>)
-                }
-            };
-
-            let to = address?;
+            let recipient = to.unwrap();
+            let to = address_book.lock().unwrap().resolve(recipient)?;
             let slate: Result<Slate, Error> = match to.address_type() {
                 AddressType::Keybase => {
                     if let Some((publisher, _)) = keybase_broker {
@@ -670,9 +754,12 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
                     }
                 },
                 AddressType::Grinbox => {
-                    if let Some((publisher, _)) = grinbox_broker {
+                    if let Some((publisher, _, _)) = grinbox_broker {
                         let slate = wallet.lock().unwrap().initiate_send_tx(amount, 10, "smallest", change_outputs, 500, message)?;
-                        publisher.post_slate(&slate, to.borrow())?;
+                        match memo {
+                            Some(memo) => publisher.post_slate_with_memo(&slate, to.borrow(), memo, DeliveryGuarantee::FireAndForget)?,
+                            None => publisher.post_slate_to(&slate, recipient)?,
+                        };
                         Ok(slate)
                     } else {
                         Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
@@ -690,7 +777,7 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
         },
         Some("invoice") => {
             let args = matches.subcommand_matches("invoice").unwrap();
-            let to = args.value_of("to").unwrap();
+            let recipient = args.value_of("to").unwrap();
             let outputs = args.value_of("outputs").unwrap_or("1");
             let outputs = usize::from_str_radix(outputs, 10)
                 .map_err(|_| {
@@ -701,22 +788,7 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
                 Wallet713Error::InvalidAmount(amount.to_string())
             })?;
 
-            let mut to = to.to_string();
-            if to.starts_with("@") {
-                let contact = address_book.lock().unwrap().get_contact(&to[1..])?;
-                to = contact.get_address().to_string();
-            }
-
-            // try parse as a general address
-            let address = Address::parse(&to);
-            let address: Result<Box<Address>, Error> = match address {
-                Ok(address) => Ok(address),
-                Err(e) => {
-                    Ok(Box::new(GrinboxAddress::from_str(&to).map_err(|_| e)?) as Box<Address>)
-                }
-            };
-
-            let to = address?;
+            let to = address_book.lock().unwrap().resolve(recipient)?;
             let slate: Result<Slate, Error> = match to.address_type() {
                 AddressType::Keybase => {
                     if let Some((publisher, _)) = keybase_broker {
@@ -728,9 +800,9 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
                     }
                 },
                 AddressType::Grinbox => {
-                    if let Some((publisher, _)) = grinbox_broker {
+                    if let Some((publisher, _, _)) = grinbox_broker {
                         let slate = wallet.lock().unwrap().initiate_receive_tx(amount, outputs)?;
-                        publisher.post_slate(&slate, to.borrow())?;
+                        publisher.post_slate_to(&slate, recipient)?;
                         Ok(slate)
                     } else {
                         Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
@@ -782,6 +854,108 @@ fn do_command(command: &str, config: &mut Wallet713Config, wallet: Arc<Mutex<Wal
             }
             return Ok(false);
         },
+        Some("grinbox") => {
+            let args = matches.subcommand_matches("grinbox").unwrap();
+            if let Some(args) = args.subcommand_matches("ping") {
+                let address = match args.value_of("address") {
+                    Some(address) => GrinboxAddress::from_str(address)?,
+                    None => config.get_grinbox_address()?,
+                };
+                let broker_config = grinbox_broker_config(config);
+                let port = relay_port(&broker_config, &address);
+                let publisher = GrinboxPublisher::with_config(&config.get_grinbox_address()?, &config.get_grinbox_secret_key()?, broker_config)?;
+                let elapsed = publisher.ping_relay(&address)?;
+                let millis = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64;
+                cli_message!("relay [{}] responded in [{}] ms",
+                    format!("{}:{}", address.domain, port).bright_green(),
+                    millis.to_string().bright_green()
+                );
+            } else if let Some(args) = args.subcommand_matches("online") {
+                if let Some((publisher, _, _)) = grinbox_broker {
+                    let to = address_book.lock().unwrap().resolve(args.value_of("address").unwrap())?;
+                    let address = GrinboxAddress::from_str(&to.to_string())?;
+                    if publisher.is_recipient_online(&address)? {
+                        cli_message!("[{}] is {}", address.stripped().bright_green(), "online".bright_green());
+                    } else {
+                        cli_message!("[{}] is {}", address.stripped().bright_green(), "offline".bright_red());
+                    }
+                } else {
+                    Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
+                }
+            } else if args.subcommand_matches("status").is_some() {
+                if let Some((_, subscriber, subscription)) = grinbox_broker {
+                    let state = match subscriber.connection_state() {
+                        ConnectionState::Connecting => "connecting".bright_yellow(),
+                        ConnectionState::Connected => "connected".bright_green(),
+                        ConnectionState::Stopped => "stopped".bright_red(),
+                    };
+                    let status = subscription.status();
+                    cli_message!("listener is [{}]", state);
+                    if let Some(since) = status.connected_since {
+                        cli_message!("connected for [{}] seconds", seconds_since(since).to_string().bright_green());
+                    }
+                    if let Some(Err(e)) = subscriber.last_close_reason().map(|reason| reason.into_result()) {
+                        cli_message!("last connection closed with: {}", e);
+                    }
+                    cli_message!("reconnects: [{}]", status.reconnects.to_string().bright_green());
+                    if let Some(at) = status.last_slate_at {
+                        cli_message!("last slate received [{}] seconds ago", seconds_since(at).to_string().bright_green());
+                    }
+                    cli_message!("slates waiting: [{}] (peak [{}] of [{}])",
+                        status.pending_slates.to_string().bright_green(),
+                        status.peak_pending_slates.to_string().bright_green(),
+                        status.queue_capacity.to_string().bright_green()
+                    );
+                    cli_message!("backpressure waits: [{}], dropped slates: [{}]",
+                        status.backpressure_waits.to_string().bright_green(),
+                        status.dropped_slates.to_string().bright_green()
+                    );
+                } else {
+                    Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
+                }
+            } else if args.subcommand_matches("pending").is_some() {
+                if let Some((publisher, _, _)) = grinbox_broker {
+                    let posts = publisher.pending_posts();
+                    if posts.is_empty() {
+                        cli_message!("no slates pending");
+                    }
+                    for post in posts {
+                        cli_message!("slate [{}] to [{}], waiting [{}] seconds after [{}] attempts",
+                            post.slate_id.bright_green(),
+                            post.recipient.bright_green(),
+                            seconds_since(post.enqueued_at).to_string().bright_green(),
+                            post.attempts.to_string().bright_green()
+                        );
+                    }
+                } else {
+                    Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
+                }
+            } else if args.subcommand_matches("retry").is_some() {
+                if let Some((publisher, _, _)) = grinbox_broker {
+                    publisher.retry_last_post()?;
+                    cli_message!("slate sent again successfully");
+                } else {
+                    Err(Wallet713Error::ClosedListener("grinbox".to_string()))?
+                }
+            } else if let Some(args) = args.subcommand_matches("receive") {
+                // the listener would take the slate first
+                if grinbox_broker.is_some() {
+                    Err(Wallet713Error::AlreadyListening("grinbox".to_string()))?
+                }
+                let timeout = args.value_of("timeout").unwrap_or("60");
+                let timeout = u64::from_str_radix(timeout, 10).map_err(|_| Wallet713Error::NumberParsingError)?;
+                let mut subscriber = GrinboxSubscriber::with_config(&config.get_grinbox_address()?, &config.get_grinbox_secret_key()?, grinbox_broker_config(config))?;
+                cli_message!("waiting for a slate...");
+                let (from, slate) = subscriber.receive_one(Duration::from_secs(timeout))?;
+                let mut file = File::create(args.value_of("file").unwrap())?;
+                slate_to_file(config, &slate, &mut file)?;
+                cli_message!("slate [{}] received from [{}] and stored in [{}]",
+                    slate.id.to_string().bright_green(),
+                    from.stripped().bright_green(),
+                    args.value_of("file").unwrap().bright_green()
+                );
+            }
+        },
         Some(subcommand) => {
             cli_message!("{}: subcommand `{}` not implemented!", "ERROR".bright_red(), subcommand.bright_green());
         },
@@ -10,6 +10,7 @@ use grin_keychain::keychain::ExtKeychain;
 use common::{Wallet713Error, Result};
 use common::config::Wallet713Config;
 use crate::common::hasher::derive_address_key;
+use crate::broker::AddressKeySource;
 
 pub struct Wallet {
     active_account: String,
@@ -309,3 +310,9 @@ impl Wallet {
         Ok(())
     }
 }
+
+impl AddressKeySource for Wallet {
+    fn address_key(&self, index: u32) -> Result<SecretKey> {
+        self.derive_address_key(index)
+    }
+}